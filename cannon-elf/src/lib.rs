@@ -0,0 +1,263 @@
+//! Load big-endian MIPS32 elf files into the initial state of the Optimism Cannon emulator
+//!
+//! This is a Rust port of `cannon load-elf`. It lays out the program segments in memory, optionally applies the
+//! same Go runtime and stack patches and writes `state.json` and `meta.json` files in the format the emulator reads. The files are semantically equivalent
+//! to those written by Go, not byte for byte identical: compressed memory pages and the order of symbols sharing
+//! an address may differ.
+//!
+//! # Examples
+//! ```no_run
+//! use cannon_elf::{load_elf, patch_stack, write_json, Metadata};
+//!
+//! let elf = std::fs::read("target/mips-unknown-none/release/my-program").unwrap();
+//! let mut state = load_elf(&elf).unwrap();
+//! patch_stack(&mut state).unwrap();
+//! write_json("state.json", &state).unwrap();
+//! write_json("meta.json", &Metadata::from_elf(&elf).unwrap()).unwrap();
+//! ```
+
+use anyhow::{bail, Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::sym::Sym;
+use goblin::elf::Elf;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+mod memory;
+mod metadata;
+mod patch;
+mod state;
+
+pub use memory::{Memory, PAGE_SIZE};
+pub use metadata::{Metadata, Symbol};
+pub use patch::{patch_go, patch_stack, STACK_POINTER};
+pub use state::{State, HEAP_START};
+
+/// Program header type of the MIPS ABI flags segment, which is not loaded
+const PT_MIPS_ABIFLAGS: u32 = 0x7000_0003;
+
+/// Create the initial emulator state by loading all program segments of the elf file into memory
+pub fn load_elf(bytes: &[u8]) -> Result<State> {
+    let elf = parse_elf(bytes)?;
+    let mut state = State::new(elf.entry as u32);
+
+    for (i, prog) in elf.program_headers.iter().enumerate() {
+        if prog.p_type == PT_MIPS_ABIFLAGS {
+            continue;
+        }
+        if prog.p_filesz != prog.p_memsz && prog.p_type != PT_LOAD {
+            bail!(
+                "program segment {} has different file size ({}) than mem size ({}): filling for non PT_LOAD segments is not supported",
+                i, prog.p_filesz, prog.p_memsz
+            );
+        }
+        if prog.p_filesz > prog.p_memsz {
+            bail!(
+                "invalid PT_LOAD program segment {}, file size ({}) > mem size ({})",
+                i,
+                prog.p_filesz,
+                prog.p_memsz
+            );
+        }
+        if prog.p_vaddr + prog.p_memsz >= 1 << 32 {
+            bail!(
+                "program {} out of 32-bit mem range: {:x} - {:x} (size: {:x})",
+                i,
+                prog.p_vaddr,
+                prog.p_vaddr + prog.p_memsz,
+                prog.p_memsz
+            );
+        }
+
+        let file_range = prog.p_offset as usize..(prog.p_offset + prog.p_filesz) as usize;
+        let mut data = bytes
+            .get(file_range)
+            .with_context(|| format!("program segment {} is outside of the elf file", i))?
+            .to_vec();
+        // zero fill the rest of the segment (e.g. .bss)
+        data.resize(prog.p_memsz as usize, 0);
+        state
+            .memory
+            .set_memory_range(prog.p_vaddr as u32, &data)
+            .with_context(|| format!("failed to read program segment {}", i))?;
+    }
+
+    Ok(state)
+}
+
+/// Write a value as json in the same layout as the Go tooling. Paths ending in `.gz` are gzip compressed
+pub fn write_json(path: impl AsRef<Path>, value: &impl Serialize) -> Result<()> {
+    let path = path.as_ref();
+    let mut json = go_json(value)?;
+    json.push(b'\n');
+    if path.extension().map_or(false, |ext| ext == "gz") {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&json)?;
+        json = encoder.finish()?;
+    }
+    std::fs::write(path, json).with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Serialize to compact json, escaping characters the same way Go's `encoding/json` does
+fn go_json(value: &impl Serialize) -> Result<Vec<u8>> {
+    let json = serde_json::to_string(value)?;
+    // These characters can only appear in strings so can be replaced safely
+    Ok(json
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+        .into_bytes())
+}
+
+fn parse_elf(bytes: &[u8]) -> Result<Elf> {
+    let elf = Elf::parse(bytes).context("Unable to parse elf file")?;
+    if elf.is_64 || elf.little_endian {
+        bail!("Cannon programs must be 32 bit big-endian MIPS elf files");
+    }
+    Ok(elf)
+}
+
+/// Entries of the elf symbol table, excluding the initial null symbol
+pub(crate) fn symbols<'a>(elf: &'a Elf) -> Result<impl Iterator<Item = (&'a str, Sym)> + 'a> {
+    if elf.syms.is_empty() {
+        bail!("failed to load symbols table: no symbol section");
+    }
+    Ok(elf
+        .syms
+        .iter()
+        .skip(1)
+        .map(|sym| (elf.strtab.get_at(sym.st_name).unwrap_or_default(), sym)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: u32 = 0x0040_0000;
+
+    /// A minimal big-endian MIPS32 elf with a code segment, an ABI flags segment and two symbols
+    fn test_elf() -> Vec<u8> {
+        let mut elf = Vec::new();
+        let u16 = |elf: &mut Vec<u8>, v: u16| elf.extend_from_slice(&v.to_be_bytes());
+        let u32 = |elf: &mut Vec<u8>, v: u32| elf.extend_from_slice(&v.to_be_bytes());
+
+        // elf header
+        elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        u16(&mut elf, 2); // executable
+        u16(&mut elf, 8); // MIPS
+        u32(&mut elf, 1);
+        u32(&mut elf, ENTRY);
+        u32(&mut elf, 52); // program headers offset
+        u32(&mut elf, 204); // section headers offset
+        u32(&mut elf, 0);
+        for v in [52, 32, 2, 40, 4, 3] {
+            u16(&mut elf, v);
+        }
+        // program headers
+        for v in [PT_LOAD, 116, ENTRY, ENTRY, 8, 0x1000, 5, 0x1000] {
+            u32(&mut elf, v);
+        }
+        for v in [PT_MIPS_ABIFLAGS, 116, 0x9000_0000, 0x9000_0000, 8, 8, 4, 8] {
+            u32(&mut elf, v);
+        }
+        // code segment
+        elf.extend_from_slice(&[0x24, 0x02, 0x0f, 0xa6, 0x00, 0x00, 0x00, 0x0c]);
+        // strtab
+        elf.extend_from_slice(b"\0_start\0foo\0");
+        // symtab, deliberately not sorted by address
+        elf.extend_from_slice(&[0; 16]);
+        for (name, value, size) in [(8, ENTRY + 4, 4), (1, ENTRY, 8)] {
+            u32(&mut elf, name);
+            u32(&mut elf, value);
+            u32(&mut elf, size);
+            elf.extend_from_slice(&[0x12, 0]);
+            u16(&mut elf, 1);
+        }
+        // section header string table
+        elf.extend_from_slice(b"\0.symtab\0.strtab\0\0\0\0");
+        // section headers: null, .symtab, .strtab, .shstrtab
+        elf.extend_from_slice(&[0; 40]);
+        for v in [1, 2, 0, 0, 136, 48, 2, 1, 4, 16] {
+            u32(&mut elf, v);
+        }
+        for v in [9, 3, 0, 0, 124, 12, 0, 0, 1, 0] {
+            u32(&mut elf, v);
+        }
+        for v in [0, 3, 0, 0, 184, 17, 0, 0, 1, 0] {
+            u32(&mut elf, v);
+        }
+        elf
+    }
+
+    #[test]
+    fn test_load_elf() {
+        let state = load_elf(&test_elf()).unwrap();
+        assert_eq!(state.pc, ENTRY);
+        assert_eq!(state.next_pc, ENTRY + 4);
+        assert_eq!(state.heap, HEAP_START);
+        assert_eq!(state.memory.get_memory(ENTRY), 0x24020fa6);
+        assert_eq!(state.memory.get_memory(ENTRY + 4), 0x0000000c);
+        assert_eq!(state.memory.get_memory(ENTRY + 8), 0);
+        // the segment and the page at its end, but not the ABI flags
+        assert_eq!(state.memory.page_count(), 2);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let metadata = Metadata::from_elf(&test_elf()).unwrap();
+        let names: Vec<_> = metadata.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["_start", "foo"]);
+        assert_eq!(
+            metadata.symbols[1],
+            Symbol {
+                name: "foo".to_string(),
+                start: ENTRY + 4,
+                size: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_patch_stack() {
+        let mut state = load_elf(&test_elf()).unwrap();
+        patch_stack(&mut state).unwrap();
+        assert_eq!(state.registers[29], STACK_POINTER);
+        assert_eq!(state.memory.get_memory(STACK_POINTER + 4), 0x42);
+        assert_eq!(
+            state.memory.get_memory(STACK_POINTER + 28),
+            STACK_POINTER + 36
+        );
+        assert_eq!(
+            state.memory.get_memory(STACK_POINTER + 36),
+            u32::from_be_bytes(*b"4;by")
+        );
+        assert_eq!(state.memory.page_count(), 2 + 6);
+    }
+
+    #[test]
+    fn test_state_json_layout() {
+        let json = String::from_utf8(go_json(&load_elf(&test_elf()).unwrap()).unwrap()).unwrap();
+        assert!(json.starts_with(r#"{"memory":[{"index":1024,"data":""#));
+        assert!(json.contains(&format!(r#""preimageKey":"0x{}","#, "0".repeat(64))));
+        assert!(json.ends_with(
+            r#""pc":4194304,"nextPC":4194308,"lo":0,"hi":0,"heap":536870912,"exit":0,"exited":false,"step":0,"registers":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}"#
+        ));
+    }
+
+    #[test]
+    fn test_go_json_escaping() {
+        let symbol = Symbol {
+            name: "<a & b>".to_string(),
+            start: 0,
+            size: 0,
+        };
+        assert_eq!(
+            go_json(&symbol).unwrap(),
+            br#"{"name":"\u003ca \u0026 b\u003e","start":0,"size":0}"#
+        );
+    }
+}
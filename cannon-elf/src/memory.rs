@@ -0,0 +1,147 @@
+use anyhow::{bail, Result};
+use base64::Engine;
+use flate2::{write::ZlibEncoder, Compression};
+use serde::ser::{Error, SerializeSeq, SerializeStruct};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Number of address bits used to index within a page
+pub const PAGE_ADDR_SIZE: u32 = 12;
+/// Size of a memory page in bytes
+pub const PAGE_SIZE: usize = 1 << PAGE_ADDR_SIZE;
+
+const PAGE_ADDR_MASK: u64 = PAGE_SIZE as u64 - 1;
+
+type Page = [u8; PAGE_SIZE];
+
+/// Sparse 32 bit address space made up of 4KiB pages, as used by the Cannon emulator
+#[derive(Debug, Default, Clone)]
+pub struct Memory {
+    pages: BTreeMap<u32, Box<Page>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write `data` into memory starting at `addr`, allocating pages as required
+    ///
+    /// This deliberately matches the Go implementation which allocates the page containing the end address
+    /// even when the data finishes exactly on a page boundary (or is empty). Memory layouts and therefore
+    /// the resulting state are identical to those produced by `cannon load-elf`.
+    pub fn set_memory_range(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        if addr as u64 + data.len() as u64 >= 1 << 32 {
+            bail!(
+                "memory range out of 32-bit address space: {:x} (size: {:x})",
+                addr,
+                data.len()
+            );
+        }
+        let mut addr = addr as u64;
+        let mut data = data;
+        loop {
+            let page = self
+                .pages
+                .entry((addr >> PAGE_ADDR_SIZE) as u32)
+                .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+            if data.is_empty() {
+                return Ok(());
+            }
+            let offset = (addr & PAGE_ADDR_MASK) as usize;
+            let n = data.len().min(PAGE_SIZE - offset);
+            page[offset..offset + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            addr += n as u64;
+        }
+    }
+
+    /// Read the big endian 32 bit word at `addr`. Unallocated memory reads as zero.
+    ///
+    /// # Panics
+    /// This will panic if `addr` is not 4 byte aligned
+    pub fn get_memory(&self, addr: u32) -> u32 {
+        assert!(addr % 4 == 0, "unaligned memory access: {:x}", addr);
+        match self.pages.get(&(addr >> PAGE_ADDR_SIZE)) {
+            Some(page) => {
+                let offset = (addr as u64 & PAGE_ADDR_MASK) as usize;
+                u32::from_be_bytes(page[offset..offset + 4].try_into().unwrap())
+            }
+            None => 0,
+        }
+    }
+
+    /// Number of allocated pages
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+/// A page is encoded as the base64 of its zlib compressed contents, as in the Go implementation. The compressed
+/// bytes may differ from those Go's `compress/zlib` writes but decompress to the same page
+struct PageData<'a>(&'a Page);
+
+impl Serialize for PageData<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(self.0).map_err(S::Error::custom)?;
+        let compressed = encoder.finish().map_err(S::Error::custom)?;
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(compressed))
+    }
+}
+
+/// Memory is encoded as a list of pages sorted by index
+impl Serialize for Memory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct PageEntry<'a>(u32, &'a Page);
+
+        impl Serialize for PageEntry<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut entry = serializer.serialize_struct("PageEntry", 2)?;
+                entry.serialize_field("index", &self.0)?;
+                entry.serialize_field("data", &PageData(self.1))?;
+                entry.end()
+            }
+        }
+
+        let mut seq = serializer.serialize_seq(Some(self.pages.len()))?;
+        for (index, page) in &self.pages {
+            seq.serialize_element(&PageEntry(*index, page))?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_end_page_is_allocated() {
+        let mut memory = Memory::new();
+        memory.set_memory_range(0x1000, &[0; PAGE_SIZE]).unwrap();
+        assert_eq!(memory.page_count(), 2);
+
+        let mut memory = Memory::new();
+        memory.set_memory_range(0x5000, &[]).unwrap();
+        assert_eq!(memory.page_count(), 1);
+    }
+
+    #[test]
+    fn test_write_across_pages() {
+        let mut memory = Memory::new();
+        memory
+            .set_memory_range(0xffe, &[0xaa, 0xbb, 0xcc, 0xdd, 0x11, 0x22])
+            .unwrap();
+        assert_eq!(memory.get_memory(0xffc), 0x0000aabb);
+        assert_eq!(memory.get_memory(0x1000), 0xccdd1122);
+        assert_eq!(memory.get_memory(0x8000), 0);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut memory = Memory::new();
+        assert!(memory.set_memory_range(0xffff_fffc, &[0; 4]).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use serde::Serialize;
+
+/// Symbol information written to `meta.json`. Cannon uses this to map addresses to symbols
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+}
+
+impl Metadata {
+    /// Collect all symbols from the symbol table of an elf file sorted by address
+    ///
+    /// Go sorts with the unstable `sort.Slice`, so symbols sharing an address may be listed in a different order
+    /// than `cannon load-elf` lists them. Here they keep their symbol table order.
+    pub fn from_elf(bytes: &[u8]) -> Result<Self> {
+        let elf = crate::parse_elf(bytes)?;
+        let mut symbols = crate::symbols(&elf)?
+            .map(|(name, sym)| Symbol {
+                name: name.to_string(),
+                start: sym.st_value as u32,
+                size: sym.st_size as u32,
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| symbol.start);
+        Ok(Self { symbols })
+    }
+}
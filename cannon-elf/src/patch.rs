@@ -0,0 +1,85 @@
+use crate::memory::PAGE_SIZE;
+use crate::state::State;
+use anyhow::{Context, Result};
+
+/// Initial stack pointer
+pub const STACK_POINTER: u32 = 0x7fff_d000;
+
+/// Go runtime functions that rely on features Cannon does not support. These are replaced with an immediate return
+const GO_NOOP_SYMBOLS: &[&str] = &[
+    // disable the Go GC
+    "runtime.gcenable",
+    // init() { go forcegchelper() }
+    "runtime.init.5",
+    // main.func() { newm(sysmon, ....) }
+    "runtime.main.func1",
+    // uses floating point and interacts with the GC
+    "runtime.deductSweepCredit",
+    "runtime.(*gcControllerState).commit",
+    // these prometheus packages rely on concurrent background things
+    "github.com/prometheus/client_golang/prometheus.init",
+    "github.com/prometheus/client_golang/prometheus.init.0",
+    "github.com/prometheus/procfs.init",
+    "github.com/prometheus/common/model.init",
+    "github.com/prometheus/client_model/go.init",
+    "github.com/prometheus/client_model/go.init.0",
+    "github.com/prometheus/client_model/go.init.1",
+    "flag.init",
+    // checks float64 NaN which is not supported
+    "runtime.check",
+];
+
+/// jr $ra followed by a nop in its branch delay slot, as in the Go patch, so the original function body is never
+/// entered
+const RETURN_INSTRUCTIONS: [u8; 8] = [0x03, 0xe0, 0x00, 0x08, 0, 0, 0, 0];
+
+/// Patch out parts of the Go runtime that cannot run in Cannon. Not needed for Rust programs
+pub fn patch_go(bytes: &[u8], state: &mut State) -> Result<()> {
+    let elf = crate::parse_elf(bytes)?;
+    for (name, sym) in crate::symbols(&elf)? {
+        if GO_NOOP_SYMBOLS.contains(&name) {
+            state
+                .memory
+                .set_memory_range(sym.st_value as u32, &RETURN_INSTRUCTIONS)
+                .with_context(|| format!("failed to patch Go {}", name))?;
+        } else if name == "runtime.MemProfileRate" {
+            // disable memory profiling to avoid a lot of unnecessary floating point ops
+            state
+                .memory
+                .set_memory_range(sym.st_value as u32, &[0; 4])?;
+        }
+    }
+    Ok(())
+}
+
+/// Allocate the stack and initialize the stack pointer, argc, argv and auxv the same as `cannon load-elf --patch stack`
+pub fn patch_stack(state: &mut State) -> Result<()> {
+    let sp = STACK_POINTER;
+    // 1 page for the initial stack data and 4 pages (16KiB) for the stack to grow
+    state
+        .memory
+        .set_memory_range(sp - 4 * PAGE_SIZE as u32, &[0; 5 * PAGE_SIZE])
+        .context("failed to allocate page for stack content")?;
+    state.registers[29] = sp;
+
+    let words = [
+        (sp + 4, 0x42),           // argc
+        (sp + 4 * 2, 0x35),       // argv[n] (terminating argv)
+        (sp + 4 * 3, 0),          // envp[term] = 0 (no env vars)
+        (sp + 4 * 4, 6),          // auxv[0] = _AT_PAGESZ = 6 (key)
+        (sp + 4 * 5, 4096),       // auxv[1] = page size of 4 KiB (value)
+        (sp + 4 * 6, 25),         // auxv[2] = AT_RANDOM
+        (sp + 4 * 7, sp + 4 * 9), // auxv[3] = address of 16 bytes containing random value
+        (sp + 4 * 8, 0),          // auxv[term] = 0
+    ];
+    for (addr, value) in words {
+        state
+            .memory
+            .set_memory_range(addr, &u32::to_be_bytes(value))?;
+    }
+    // 16 bytes of "randomness"
+    state
+        .memory
+        .set_memory_range(sp + 4 * 9, b"4;byfairdiceroll")?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use crate::memory::Memory;
+use serde::{Serialize, Serializer};
+
+/// Initial top of the heap used for mmap growth
+pub const HEAP_START: u32 = 0x2000_0000;
+
+/// The Cannon emulator state as written to `state.json`
+///
+/// Fields are serialized in the same order and with the same names as the Go `mipsevm.State`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct State {
+    pub memory: Memory,
+    #[serde(serialize_with = "serialize_hash")]
+    pub preimage_key: [u8; 32],
+    /// Note that the offset includes the 8 byte length prefix
+    pub preimage_offset: u32,
+    pub pc: u32,
+    #[serde(rename = "nextPC")]
+    pub next_pc: u32,
+    pub lo: u32,
+    pub hi: u32,
+    pub heap: u32,
+    #[serde(rename = "exit")]
+    pub exit_code: u8,
+    pub exited: bool,
+    pub step: u64,
+    pub registers: [u32; 32],
+}
+
+impl State {
+    /// State ready to start executing at `entry`
+    pub fn new(entry: u32) -> Self {
+        Self {
+            memory: Memory::new(),
+            preimage_key: [0; 32],
+            preimage_offset: 0,
+            pc: entry,
+            next_pc: entry.wrapping_add(4),
+            lo: 0,
+            hi: 0,
+            heap: HEAP_START,
+            exit_code: 0,
+            exited: false,
+            step: 0,
+            registers: [0; 32],
+        }
+    }
+}
+
+fn serialize_hash<S: Serializer>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{}", hex::encode(hash)))
+}
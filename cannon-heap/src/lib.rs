@@ -1,6 +1,7 @@
+//! This is actually just a wrapper around linked_list_allocator that allows it to work in our environment
+//! Different allocator can be used if desired
 #![no_std]
-///! This is actually just a wrapper around linked_list_allocator that allows it to work in our environment
-///! Different allocator can be used if desired
+
 use core::alloc::{GlobalAlloc, Layout};
 use core::cell::RefCell;
 use core::mem::MaybeUninit;
@@ -33,9 +34,14 @@ unsafe impl GlobalAlloc for Alloc {
     }
 }
 
-#[global_allocator]
+// Only install the allocator on the Cannon target so native builds (e.g. unit tests of guest code) use the system allocator
+#[cfg_attr(target_arch = "mips", global_allocator)]
 static mut ALLOCATOR: Alloc = Alloc::new();
 
+/// Initialize the global allocator to use the given memory as its heap. Prefer the `init_heap!` macro.
+///
+/// # Safety
+/// Must only be called once and the heap memory must not be used for anything else for the rest of the program
 pub unsafe fn init(heap: &mut [MaybeUninit<u8>]) {
     ALLOCATOR
         .heap
@@ -0,0 +1,97 @@
+use crate::syscalls::{self, SyscallError};
+
+#[derive(Debug)]
+pub enum HintError {
+    /// The hint is too large for its length to be encoded in the 4 byte prefix
+    TooLarge,
+    /// The host stopped accepting data before the whole hint was written
+    WriteZero,
+    /// The host closed the hint channel without acknowledging the hint
+    NoAck,
+    SyscallError(syscalls::SyscallError),
+}
+
+impl From<SyscallError> for HintError {
+    fn from(e: SyscallError) -> Self {
+        HintError::SyscallError(e)
+    }
+}
+
+/// Sends hints to the host using the fault proof hint protocol
+///
+/// Each hint is written to the HintWrite file descriptor as a 4 byte big endian length prefix followed by the hint itself.
+/// The host then acknowledges it by writing a single byte to the HintRead file descriptor once it has processed the hint,
+/// for example by fetching the preimages it refers to. See https://github.com/ethereum-optimism/optimism/blob/develop/specs/fault-proof.md#hinting
+#[derive(Debug, Default)]
+pub struct HintWriter;
+
+impl HintWriter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Send a hint to the host and wait for it to be acknowledged
+    ///
+    /// The host may only accept a few bytes at a time so this will write in a loop until the whole hint has been sent.
+    ///
+    /// # Examples
+    /// ```
+    /// use cannon_io::prelude::*;
+    ///
+    /// let mut hints = HintWriter::new();
+    /// hints.hint("l1-block-header 0x0102030405060708091011121314151617181920212223242526272829303132").unwrap();
+    /// ```
+    pub fn hint(&mut self, hint: impl AsRef<[u8]>) -> Result<(), HintError> {
+        let hint = hint.as_ref();
+        let length = u32::try_from(hint.len()).map_err(|_| HintError::TooLarge)?;
+        write_all(&length.to_be_bytes())?;
+        write_all(hint)?;
+
+        let mut ack = [0_u8; 1];
+        match syscalls::read_hint(&mut ack)? {
+            0 => Err(HintError::NoAck),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn write_all(buf: &[u8]) -> Result<(), HintError> {
+    let mut written = 0;
+    while written < buf.len() {
+        match syscalls::write_hint(&buf[written..])? {
+            0 => return Err(HintError::WriteZero),
+            n => written += n as usize,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscalls::mock;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_hint_is_length_prefixed() {
+        mock::reset();
+        HintWriter::new().hint("l1-block-header 0xabcd").unwrap();
+        let mut expected = 22_u32.to_be_bytes().to_vec();
+        expected.extend_from_slice(b"l1-block-header 0xabcd");
+        assert_eq!(mock::hints_written(), expected);
+    }
+
+    #[test]
+    fn test_hints_with_partial_writes() {
+        mock::reset();
+        mock::set_max_io_size(Some(3));
+        let mut hints = HintWriter::new();
+        hints.hint("first").unwrap();
+        hints.hint([0xff; 100]).unwrap();
+        hints.hint([]).unwrap();
+        assert_eq!(
+            mock::hints(),
+            [b"first".to_vec(), [0xff; 100].to_vec(), Vec::new()]
+        );
+    }
+}
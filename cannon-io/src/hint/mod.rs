@@ -0,0 +1,4 @@
+//! Send hints to the host to tell it which preimages the guest is about to request
+mod hint_writer;
+
+pub use hint_writer::{HintError, HintWriter};
@@ -1,4 +1,4 @@
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 /// Types of preimage oracle keys. See https://github.com/ethereum-optimism/optimism/blob/develop/specs/fault-proof.md#pre-image-key-types
 pub enum KeyType {
     /// Local key types are local and context dependent.
@@ -13,6 +13,21 @@ pub enum KeyType {
     Sha256 = 129,
 }
 
+/// Decode the type byte of a key. Unknown types are returned as the error
+impl TryFrom<u8> for KeyType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(KeyType::Local),
+            2 => Ok(KeyType::Keccak256),
+            3 => Ok(KeyType::Generic),
+            129 => Ok(KeyType::Sha256),
+            other => Err(other),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct PreimageKey {
     pub key_type: KeyType,
@@ -73,4 +88,17 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn test_key_type_from_byte() {
+        for key_type in [
+            KeyType::Local,
+            KeyType::Keccak256,
+            KeyType::Generic,
+            KeyType::Sha256,
+        ] {
+            assert_eq!(KeyType::try_from(key_type as u8), Ok(key_type));
+        }
+        assert_eq!(KeyType::try_from(4), Err(4));
+    }
 }
@@ -1,6 +1,11 @@
 //! Interact with the host preimage oracle to retrieve data by its key
 mod key;
 mod oracle_reader;
+#[cfg(any(feature = "keccak256", feature = "sha256"))]
+mod verify;
 
-pub use key::PreimageKey;
-pub use oracle_reader::{oracle_reader, OracleReader, Read};
+pub use key::{KeyType, PreimageKey};
+pub use oracle_reader::{oracle_reader, OracleError, OracleReader, Read};
+
+#[cfg(not(target_arch = "mips"))]
+pub(crate) use oracle_reader::reset_oracle_reader;
@@ -1,12 +1,18 @@
 use crate::syscalls::{self, SyscallError};
+use alloc::vec;
 use alloc::vec::Vec;
 
-pub use super::PreimageKey;
+pub use super::{KeyType, PreimageKey};
 
 #[derive(Debug)]
 pub enum OracleError {
     NoKeySet,
     EndOfData,
+    /// The data returned by the host does not hash to the requested key
+    HashMismatch,
+    /// Data for this key type cannot be verified. Local and generic keys are not hashes and hash based
+    /// key types can only be verified if the corresponding crate feature is enabled
+    UnverifiableKeyType(KeyType),
     SyscallError(syscalls::SyscallError),
 }
 
@@ -22,14 +28,23 @@ pub struct OracleReader {
     cursor: u64,
 }
 
-// the only way to access an oracle reader is through this singleton.
-// This is to ensure there cannot be more than one at a time which would have
-// unpredictable results
-static mut ORACLE_READER: Option<OracleReader> = Some(OracleReader {
+const NEW_ORACLE_READER: OracleReader = OracleReader {
     key: None,
     length: 0,
     cursor: 0,
-});
+};
+
+// the only way to access an oracle reader is through this singleton.
+// This is to ensure there cannot be more than one at a time which would have
+// unpredictable results
+#[cfg(target_arch = "mips")]
+static mut ORACLE_READER: Option<OracleReader> = Some(NEW_ORACLE_READER);
+
+// The mock kernel is per-thread so the singleton is too
+#[cfg(not(target_arch = "mips"))]
+std::thread_local! {
+    static ORACLE_READER: core::cell::Cell<Option<OracleReader>> = core::cell::Cell::new(Some(NEW_ORACLE_READER));
+}
 
 /// Get the global oracle reader
 ///
@@ -37,10 +52,17 @@ static mut ORACLE_READER: Option<OracleReader> = Some(OracleReader {
 /// This will panic if called more than once. This is to ensure there is only one oracle reader at once
 /// as it encapsulates host global state.
 pub fn oracle_reader() -> OracleReader {
-    unsafe {
-        let reader = core::ptr::replace(&mut ORACLE_READER, None);
-        reader.expect("oracle_reader` has already been called. Can only call once per program")
-    }
+    #[cfg(target_arch = "mips")]
+    let reader = unsafe { core::ptr::replace(&mut ORACLE_READER, None) };
+    #[cfg(not(target_arch = "mips"))]
+    let reader = ORACLE_READER.with(|reader| reader.take());
+    reader.expect("oracle_reader` has already been called. Can only call once per program")
+}
+
+/// Allow the oracle reader to be retrieved again when the mock kernel is reset
+#[cfg(not(target_arch = "mips"))]
+pub(crate) fn reset_oracle_reader() {
+    ORACLE_READER.with(|reader| reader.set(Some(NEW_ORACLE_READER)));
 }
 
 impl OracleReader {
@@ -55,6 +77,7 @@ impl OracleReader {
     /// # Examples
     /// ```
     /// use cannon_io::prelude::*;
+    /// # cannon_io::syscalls::mock::set_preimage(PreimageKey::new_local(&[0xff;31]), [0x1e, 0xe7]);
     ///
     /// let mut oracle = oracle_reader();
     /// oracle.set_key(PreimageKey::new_local(&[0xff;31]));
@@ -109,12 +132,12 @@ impl OracleReader {
     ///
     /// let mut oracle = oracle_reader();
     /// let key = PreimageKey::new_local(&[0xff;31]);
+    /// # cannon_io::syscalls::mock::set_preimage(key, [0x1e, 0xe7]);
     /// let data = oracle.get(key).unwrap();
     /// ```
     pub fn get(&mut self, key: PreimageKey) -> Result<Vec<u8>, OracleError> {
         self.set_key(key)?;
-        let mut data_buffer = Vec::with_capacity(self.length as usize);
-        data_buffer.resize(self.length as usize, 0);
+        let mut data_buffer = vec![0; self.length as usize];
         self.read_exact(&mut data_buffer)?;
         Ok(data_buffer)
     }
@@ -130,6 +153,7 @@ impl OracleReader {
     ///
     /// let mut oracle = oracle_reader();
     /// let key = PreimageKey::new_local(&[0xff;31]);
+    /// # cannon_io::syscalls::mock::set_preimage(key, [0_u8; 100]);
     /// let mut buffer = [0_u8; 100];
     /// oracle.get_exact(key, &mut buffer).unwrap();
     /// ```
@@ -139,6 +163,31 @@ impl OracleReader {
         self.read_exact(buf)?;
         Ok(())
     }
+
+    /// Same as [`get`](Self::get) but the data is hashed in the guest to check it matches the key
+    ///
+    /// Requires the `keccak256` and/or `sha256` crate features for the corresponding key types.
+    /// Returns [`OracleError::HashMismatch`] if the host returned the wrong data.
+    #[cfg(any(feature = "keccak256", feature = "sha256"))]
+    pub fn get_verified(&mut self, key: PreimageKey) -> Result<Vec<u8>, OracleError> {
+        let data = self.get(key)?;
+        super::verify::verify(key, &data)?;
+        Ok(data)
+    }
+
+    /// Same as [`get_exact`](Self::get_exact) but the data is hashed in the guest to check it matches the key
+    ///
+    /// Requires the `keccak256` and/or `sha256` crate features for the corresponding key types.
+    /// Returns [`OracleError::HashMismatch`] if the host returned the wrong data.
+    #[cfg(any(feature = "keccak256", feature = "sha256"))]
+    pub fn get_exact_verified(
+        &mut self,
+        key: PreimageKey,
+        buf: &mut [u8],
+    ) -> Result<(), OracleError> {
+        self.get_exact(key, buf)?;
+        super::verify::verify(key, buf)
+    }
 }
 
 // Since the Rust Error trait cannot be used in no_std, we define our own
@@ -195,7 +244,9 @@ impl Read for OracleReader {
         let mut chunk = [0; 32];
         let mut read = 0;
         while read < buf.len() {
-            let chunk_read = self.read(&mut chunk)?;
+            // never request more than is still needed so the cursor is left just after buf
+            let chunk_len = chunk.len().min(buf.len() - read);
+            let chunk_read = self.read(&mut chunk[..chunk_len])?;
             if chunk_read == 0 {
                 return Err(OracleError::EndOfData);
             }
@@ -0,0 +1,102 @@
+//! In-guest verification that preimage data matches its key
+use super::key::{KeyType, PreimageKey};
+use super::oracle_reader::OracleError;
+
+/// Check the data hashes to the key. The first byte of the hash is ignored as it is replaced by the key type.
+pub(crate) fn verify(key: PreimageKey, data: &[u8]) -> Result<(), OracleError> {
+    let hash = match key.key_type {
+        #[cfg(feature = "keccak256")]
+        KeyType::Keccak256 => keccak256(data),
+        #[cfg(feature = "sha256")]
+        KeyType::Sha256 => sha256(data),
+        key_type => return Err(OracleError::UnverifiableKeyType(key_type)),
+    };
+    if hash[1..] == key.x {
+        Ok(())
+    } else {
+        Err(OracleError::HashMismatch)
+    }
+}
+
+#[cfg(feature = "keccak256")]
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    use tiny_keccak::{Hasher, Keccak};
+
+    let mut hash = [0; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(data);
+    keccak.finalize(&mut hash);
+    hash
+}
+
+#[cfg(feature = "sha256")]
+fn sha256(data: &[u8]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // keccak256 and sha256 of the empty string
+    #[cfg(feature = "keccak256")]
+    const KECCAK_EMPTY: [u8; 32] = [
+        0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03,
+        0xc0, 0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85,
+        0xa4, 0x70,
+    ];
+    #[cfg(feature = "sha256")]
+    const SHA256_EMPTY: [u8; 32] = [
+        0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9,
+        0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52,
+        0xb8, 0x55,
+    ];
+
+    #[test]
+    #[cfg(feature = "keccak256")]
+    fn test_keccak256() {
+        assert!(verify(PreimageKey::new_keccak(KECCAK_EMPTY), &[]).is_ok());
+        assert!(matches!(
+            verify(PreimageKey::new_keccak(KECCAK_EMPTY), &[0]),
+            Err(OracleError::HashMismatch)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "sha256")]
+    fn test_sha256() {
+        assert!(verify(PreimageKey::new_sha256(SHA256_EMPTY), &[]).is_ok());
+        assert!(matches!(
+            verify(PreimageKey::new_sha256(SHA256_EMPTY), &[0]),
+            Err(OracleError::HashMismatch)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "keccak256")]
+    fn test_get_verified_rejects_wrong_data() {
+        use crate::oracle::oracle_reader;
+        use crate::syscalls::mock;
+
+        mock::reset();
+        let key = PreimageKey::new_keccak(KECCAK_EMPTY);
+        let mut oracle = oracle_reader();
+        mock::set_preimage(key, []);
+        assert!(oracle.get_verified(key).unwrap().is_empty());
+        mock::set_preimage(key, [0]);
+        assert!(matches!(
+            oracle.get_verified(key),
+            Err(OracleError::HashMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_local_keys_cannot_be_verified() {
+        assert!(matches!(
+            verify(PreimageKey::new_local(&[1]), &[]),
+            Err(OracleError::UnverifiableKeyType(KeyType::Local))
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+//! A simulated Cannon kernel that handles syscalls when not building for MIPS
+//!
+//! This allows guest logic that prints, reads preimages, sends hints or exits to be tested natively
+//! with a plain `cargo test`. Every thread has its own kernel so tests running in parallel cannot interfere
+//! with each other. Use the functions in this module to populate the preimage oracle and hint channel
+//! before running guest code and to inspect what it wrote afterwards.
+//!
+//! # Examples
+//! ```
+//! use cannon_io::prelude::*;
+//! use cannon_io::syscalls::mock::{self, Exit};
+//!
+//! let key = PreimageKey::new_local(&[0xff]);
+//! mock::set_preimage(key, [0x1e, 0xe7]);
+//!
+//! let result = mock::catch_exit(|| {
+//!     let data = oracle_reader().get(key).unwrap();
+//!     print("got the data").unwrap();
+//!     exit(data.len() as u8);
+//! });
+//!
+//! assert_eq!(result, Err(Exit(2)));
+//! assert_eq!(mock::stdout(), b"got the data");
+//! ```
+
+use super::FileDescriptor;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::{self, UnwindSafe};
+
+/// Error code returned when reading from or writing to a file descriptor that does not support it
+const EBADF: i32 = 9;
+
+/// Raised when the guest calls `exit` while running against the mock kernel
+///
+/// This unwinds the guest like a panic (without invoking the panic hook) and can be recovered with [`catch_exit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exit(pub u8);
+
+#[derive(Default)]
+struct Kernel {
+    stdout: Vec<u8>,
+    preimages: HashMap<[u8; 32], Vec<u8>>,
+    preimage_key: [u8; 32],
+    preimage_offset: usize,
+    hint_write: Vec<u8>,
+    hint_pending: Vec<u8>,
+    hints: Vec<Vec<u8>>,
+    hint_read: VecDeque<u8>,
+    max_io_size: Option<usize>,
+    exit_code: Option<u8>,
+}
+
+std::thread_local! {
+    static KERNEL: RefCell<Kernel> = RefCell::new(Kernel::default());
+}
+
+fn with_kernel<R>(f: impl FnOnce(&mut Kernel) -> R) -> R {
+    KERNEL.with(|kernel| f(&mut kernel.borrow_mut()))
+}
+
+/// Reset the mock kernel for the current thread to its initial state
+///
+/// This clears all preimages, captured output and hint data and also releases the `oracle_reader` singleton
+/// so it can be retrieved again.
+pub fn reset() {
+    with_kernel(|kernel| *kernel = Kernel::default());
+    crate::oracle::reset_oracle_reader();
+}
+
+/// Make a preimage available to the guest under the given key
+pub fn set_preimage(key: impl Into<[u8; 32]>, data: impl Into<Vec<u8>>) {
+    let (key, data) = (key.into(), data.into());
+    with_kernel(|kernel| kernel.preimages.insert(key, data));
+}
+
+/// Limit the number of bytes that a single read or write on the preimage and hint file descriptors can transfer
+///
+/// The real host may only accept or return a few bytes at a time. Setting this allows testing that the guest handles
+/// partial reads and writes correctly. `None` (the default) removes the limit.
+pub fn set_max_io_size(max: Option<usize>) {
+    with_kernel(|kernel| kernel.max_io_size = max);
+}
+
+/// Queue bytes to be returned to the guest when it reads from the hint read file descriptor
+pub fn push_hint_response(data: &[u8]) {
+    with_kernel(|kernel| kernel.hint_read.extend(data));
+}
+
+/// All bytes the guest has written to stdout
+pub fn stdout() -> Vec<u8> {
+    with_kernel(|kernel| kernel.stdout.clone())
+}
+
+/// All bytes the guest has written to the hint write file descriptor
+pub fn hints_written() -> Vec<u8> {
+    with_kernel(|kernel| kernel.hint_write.clone())
+}
+
+/// All complete length prefixed hints the guest has sent, in order
+pub fn hints() -> Vec<Vec<u8>> {
+    with_kernel(|kernel| kernel.hints.clone())
+}
+
+/// The code passed to `exit` if the guest has exited
+pub fn exit_code() -> Option<u8> {
+    with_kernel(|kernel| kernel.exit_code)
+}
+
+/// Run some guest code, returning `Err(Exit(code))` if it calls `exit` instead of returning normally
+///
+/// Any other panic raised by `f` is propagated.
+pub fn catch_exit<R>(f: impl FnOnce() -> R + UnwindSafe) -> Result<R, Exit> {
+    panic::catch_unwind(f).map_err(|payload| match payload.downcast::<Exit>() {
+        Ok(exit) => *exit,
+        Err(payload) => panic::resume_unwind(payload),
+    })
+}
+
+pub(super) fn exit(code: u8) -> ! {
+    with_kernel(|kernel| kernel.exit_code = Some(code));
+    panic::resume_unwind(Box::new(Exit(code)))
+}
+
+pub(super) fn write(fd: FileDescriptor, buf: &[u8]) -> Result<u32, i32> {
+    with_kernel(|kernel| {
+        match fd {
+            FileDescriptor::StdOut => {
+                kernel.stdout.extend_from_slice(buf);
+                return Ok(buf.len() as u32);
+            }
+            FileDescriptor::HintRead | FileDescriptor::PreimageRead => return Err(EBADF),
+            _ => {}
+        }
+        let buf = &buf[..kernel.io_size(buf.len())];
+        if fd == FileDescriptor::HintWrite {
+            kernel.hint_write.extend_from_slice(buf);
+            kernel.hint_pending.extend_from_slice(buf);
+            kernel.process_hints();
+        } else {
+            // As in the Cannon emulator the written bytes are shifted into the key and the read offset is reset
+            let n = buf.len().min(32);
+            kernel.preimage_key.rotate_left(n);
+            kernel.preimage_key[32 - n..].copy_from_slice(&buf[buf.len() - n..]);
+            kernel.preimage_offset = 0;
+        }
+        Ok(buf.len() as u32)
+    })
+}
+
+pub(super) fn read(fd: FileDescriptor, buf: &mut [u8]) -> Result<u32, i32> {
+    with_kernel(|kernel| {
+        let n = kernel.io_size(buf.len());
+        match fd {
+            FileDescriptor::HintRead => {
+                let n = n.min(kernel.hint_read.len());
+                for (dst, src) in buf.iter_mut().zip(kernel.hint_read.drain(..n)) {
+                    *dst = src;
+                }
+                Ok(n as u32)
+            }
+            FileDescriptor::PreimageRead => {
+                let data = kernel
+                    .preimages
+                    .get(&kernel.preimage_key)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "mock kernel: guest requested preimage that does not exist: {:02x?}",
+                            kernel.preimage_key
+                        )
+                    });
+                // The preimage is served with its length prefix as a big endian u64
+                let length = (data.len() as u64).to_be_bytes();
+                let served = length
+                    .iter()
+                    .chain(data)
+                    .skip(kernel.preimage_offset)
+                    .take(n);
+                let mut read = 0;
+                for (dst, src) in buf.iter_mut().zip(served) {
+                    *dst = *src;
+                    read += 1;
+                }
+                kernel.preimage_offset += read;
+                Ok(read as u32)
+            }
+            _ => Err(EBADF),
+        }
+    })
+}
+
+impl Kernel {
+    /// Like the host, acknowledge each complete hint by making a single byte available on the hint read file descriptor
+    fn process_hints(&mut self) {
+        while self.hint_pending.len() >= 4 {
+            let length = u32::from_be_bytes(self.hint_pending[..4].try_into().unwrap()) as usize;
+            if self.hint_pending.len() < 4 + length {
+                break;
+            }
+            let frame: Vec<u8> = self.hint_pending.drain(..4 + length).collect();
+            self.hints.push(frame[4..].to_vec());
+            self.hint_read.push_back(0);
+        }
+    }
+
+    fn io_size(&self, requested: usize) -> usize {
+        self.max_io_size.map_or(requested, |max| requested.min(max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oracle::{oracle_reader, PreimageKey, Read};
+    use crate::syscalls::{print, read_hint, write_hint};
+
+    #[test]
+    fn test_stdout_is_captured() {
+        reset();
+        print("hello ").unwrap();
+        print("world").unwrap();
+        assert_eq!(stdout(), b"hello world");
+    }
+
+    #[test]
+    fn test_exit_is_recoverable() {
+        reset();
+        assert_eq!(catch_exit(|| super::super::exit(3)), Err(Exit(3)));
+        assert_eq!(exit_code(), Some(3));
+        assert_eq!(catch_exit(|| 7), Ok(7));
+    }
+
+    #[test]
+    fn test_oracle_reader_with_partial_io() {
+        reset();
+        set_max_io_size(Some(3));
+        let key = PreimageKey::new_keccak([0xab; 32]);
+        let data: Vec<u8> = (0..100).collect();
+        set_preimage(key, data.clone());
+
+        let mut oracle = oracle_reader();
+        assert_eq!(oracle.get(key).unwrap(), data);
+        // setting the key again restarts the stream from the length prefix
+        oracle.set_key(key).unwrap();
+        assert_eq!(oracle.length(), 100);
+        let mut rest = Vec::new();
+        oracle.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data);
+    }
+
+    #[test]
+    fn test_reset_releases_oracle_reader() {
+        reset();
+        let _ = oracle_reader();
+        reset();
+        let _ = oracle_reader();
+    }
+
+    #[test]
+    #[should_panic(expected = "preimage that does not exist")]
+    fn test_missing_preimage_panics() {
+        reset();
+        let _ = oracle_reader().get(PreimageKey::new_local(&[1]));
+    }
+
+    #[test]
+    fn test_hint_channels() {
+        reset();
+        write_hint(&[0x11; 32]).unwrap();
+        assert_eq!(hints_written(), [0x11; 32]);
+
+        push_hint_response(&[1, 2]);
+        let mut buf = [0; 4];
+        assert_eq!(read_hint(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [1, 2]);
+        assert_eq!(read_hint(&mut buf).unwrap(), 0);
+    }
+}
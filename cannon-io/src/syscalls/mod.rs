@@ -1,21 +1,17 @@
 //! Low level access to syscalls that are understood by the minimal Cannon kernel
 //! Using these can be dangerous. Prefer to use the oracle_reader if possible
 
+#[cfg(target_arch = "mips")]
 use raw::{syscall1, syscall3};
 
 #[cfg(target_arch = "mips")]
 mod raw;
-// Provide a mock non-mips implementation so that rust-analyzer and cargo check work correctly
+// On any other target the syscalls are handled by an in-process simulated kernel
+// so guest code can be unit tested natively
 #[cfg(not(target_arch = "mips"))]
-mod raw {
-    pub unsafe fn syscall1(_: u32, _: u32) -> u32 {
-        todo!("Crate can only work on MIPS target")
-    }
-    pub unsafe fn syscall3(_: u32, _: u32, _: u32, _: u32) -> Result<u32, i32> {
-        todo!("Crate can only work on MIPS target")
-    }
-}
+pub mod mock;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FileDescriptor {
     StdOut = 1,
     HintRead = 3,
@@ -23,6 +19,7 @@ enum FileDescriptor {
     PreimageRead = 5,
     PreimageWrite = 6,
 }
+#[cfg(target_arch = "mips")]
 enum SyscallNo {
     Exit = 4246,
     Read = 4003,
@@ -54,14 +51,16 @@ pub fn read_preimage(out: &mut [u8]) -> Result<u32> {
     read(FileDescriptor::PreimageRead, out)
 }
 
-pub fn write_hint(key: [u8; 32]) -> Result<u32> {
-    write(FileDescriptor::HintWrite, &key)
+/// Write raw bytes to the hint channel. Prefer the `HintWriter` which implements the hint protocol
+pub fn write_hint(buf: &[u8]) -> Result<u32> {
+    write(FileDescriptor::HintWrite, buf)
 }
 
 pub fn read_hint(out: &mut [u8]) -> Result<u32> {
     read(FileDescriptor::HintRead, out)
 }
 
+#[cfg(target_arch = "mips")]
 pub fn exit(code: u8) -> ! {
     unsafe {
         syscall1(SyscallNo::Exit as u32, code.into());
@@ -69,6 +68,12 @@ pub fn exit(code: u8) -> ! {
     }
 }
 
+#[cfg(not(target_arch = "mips"))]
+pub fn exit(code: u8) -> ! {
+    mock::exit(code)
+}
+
+#[cfg(target_arch = "mips")]
 fn write(fd: FileDescriptor, buf: &[u8]) -> Result<u32> {
     let result = unsafe {
         syscall3(
@@ -81,6 +86,7 @@ fn write(fd: FileDescriptor, buf: &[u8]) -> Result<u32> {
     result.map_err(SyscallError::from)
 }
 
+#[cfg(target_arch = "mips")]
 fn read(fd: FileDescriptor, buf: &mut [u8]) -> Result<u32> {
     let result = unsafe {
         syscall3(
@@ -92,3 +98,13 @@ fn read(fd: FileDescriptor, buf: &mut [u8]) -> Result<u32> {
     };
     result.map_err(SyscallError::from)
 }
+
+#[cfg(not(target_arch = "mips"))]
+fn write(fd: FileDescriptor, buf: &[u8]) -> Result<u32> {
+    mock::write(fd, buf).map_err(SyscallError::from)
+}
+
+#[cfg(not(target_arch = "mips"))]
+fn read(fd: FileDescriptor, buf: &mut [u8]) -> Result<u32> {
+    mock::read(fd, buf).map_err(SyscallError::from)
+}
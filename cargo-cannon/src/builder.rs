@@ -0,0 +1,303 @@
+//! Cross compile a Cannon program by running cargo inside the Cannon-rs builder docker image
+
+use crate::cli;
+use anyhow::{bail, Context, Result};
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{Message, MetadataCommand};
+use std::ffi::OsString;
+use std::io::BufReader;
+use std::process::{Command, Stdio};
+
+/// Where the workspace is mounted inside the builder container
+const CONTAINER_WORKSPACE: &str = "/code";
+/// Where a target directory outside of the workspace is mounted inside the builder container
+const CONTAINER_TARGET_DIR: &str = "/target";
+
+/// A host directory mounted into the builder container
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    host: Utf8PathBuf,
+    container: Utf8PathBuf,
+}
+
+impl Mount {
+    fn to_container(&self, path: &Utf8Path) -> Option<Utf8PathBuf> {
+        let relative = path.strip_prefix(&self.host).ok()?;
+        Some(self.container.join(relative))
+    }
+
+    fn to_host(&self, path: &Utf8Path) -> Option<Utf8PathBuf> {
+        let relative = path.strip_prefix(&self.container).ok()?;
+        Some(self.host.join(relative))
+    }
+}
+
+/// Build the program described by `args` in docker and return the exit code of the build
+///
+/// On success the paths of all produced MIPS ELF files are printed
+pub fn build(args: cli::Build) -> Result<i32> {
+    let mut metadata = MetadataCommand::new();
+    metadata.no_deps();
+    if let Some(manifest_path) = &args.manifest_path {
+        metadata.manifest_path(manifest_path);
+    }
+    let metadata = metadata.exec().context("Unable to read cargo metadata")?;
+
+    let mounts = workspace_mounts(&metadata.workspace_root, args.target_dir.as_deref())?;
+    let cargo_args = cargo_args(&args, &mounts)?;
+
+    let mut child = Command::new("docker")
+        .args(docker_args(&args.docker_image, &mounts))
+        .args(cargo_args)
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Unable to run docker. Is it installed?")?;
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut elfs = Vec::new();
+    for message in Message::parse_stream(BufReader::new(stdout)) {
+        match message.context("Unable to read cargo output")? {
+            Message::CompilerArtifact(artifact) => {
+                if let Some(executable) = artifact.executable {
+                    let host_path = mounts.iter().find_map(|m| m.to_host(&executable));
+                    elfs.push(host_path.unwrap_or(executable));
+                }
+            }
+            Message::TextLine(line) => println!("{}", line),
+            _ => {}
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Ok(status.code().unwrap_or(1));
+    }
+    for elf in elfs {
+        println!("Built Cannon program at {}", elf);
+    }
+    Ok(0)
+}
+
+/// The workspace is always mounted. A target directory is mounted separately only if it is outside of the workspace
+fn workspace_mounts(
+    workspace_root: &Utf8Path,
+    target_dir: Option<&std::path::Path>,
+) -> Result<Vec<Mount>> {
+    let mut mounts = vec![Mount {
+        host: workspace_root.to_owned(),
+        container: CONTAINER_WORKSPACE.into(),
+    }];
+    if let Some(target_dir) = target_dir {
+        let target_dir = absolute(target_dir)?;
+        if !target_dir.starts_with(workspace_root) {
+            std::fs::create_dir_all(&target_dir)
+                .with_context(|| format!("Unable to create target directory {}", target_dir))?;
+            mounts.push(Mount {
+                host: target_dir,
+                container: CONTAINER_TARGET_DIR.into(),
+            });
+        }
+    }
+    Ok(mounts)
+}
+
+fn docker_args(image: &str, mounts: &[Mount]) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec!["run".into(), "--rm".into()];
+    for mount in mounts {
+        args.push("-v".into());
+        args.push(format!("{}:{}", mount.host, mount.container).into());
+    }
+    args.push(format!("-w={}", CONTAINER_WORKSPACE).into());
+    args.push(image.into());
+    args
+}
+
+/// Arguments for the cargo invocation inside the container with any paths translated to container paths
+fn cargo_args(args: &cli::Build, mounts: &[Mount]) -> Result<Vec<OsString>> {
+    let mut cargo: Vec<OsString> = vec![
+        "cargo".into(),
+        "build".into(),
+        "--release".into(),
+        "-Zbuild-std".into(),
+        "--message-format=json-render-diagnostics".into(),
+    ];
+    if let Some(features) = &args.features {
+        cargo.push("--features".into());
+        cargo.push(features.into());
+    }
+    if args.all_features {
+        cargo.push("--all-features".into());
+    }
+    if args.no_default_features {
+        cargo.push("--no-default-features".into());
+    }
+    if let Some(target_dir) = &args.target_dir {
+        cargo.push("--target-dir".into());
+        cargo.push(container_path(target_dir, mounts)?.into());
+    }
+    if let Some(manifest_path) = &args.manifest_path {
+        cargo.push("--manifest-path".into());
+        cargo.push(container_path(manifest_path, mounts)?.into());
+    }
+    match &args.package {
+        Some(Some(spec)) => {
+            cargo.push("--package".into());
+            cargo.push(spec.into());
+        }
+        Some(None) => cargo.push("--package".into()),
+        None => {}
+    }
+    Ok(cargo)
+}
+
+fn container_path(path: &std::path::Path, mounts: &[Mount]) -> Result<Utf8PathBuf> {
+    let path = absolute(path)?;
+    match mounts.iter().find_map(|m| m.to_container(&path)) {
+        Some(path) => Ok(path),
+        None => bail!("{} is not inside the workspace being built", path),
+    }
+}
+
+/// The canonical form of `path`, which need not exist yet. Its longest existing ancestor is canonicalized, resolving
+/// symlinks, and the remaining components are resolved lexically
+fn absolute(path: &std::path::Path) -> Result<Utf8PathBuf> {
+    let path = std::env::current_dir()?.join(path);
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            // a trailing `..` or the root, which are resolved below
+            _ => break,
+        }
+    }
+    let mut path = existing
+        .canonicalize()
+        .unwrap_or_else(|_| existing.to_owned());
+    for name in rest.into_iter().rev() {
+        path.push(name);
+    }
+    let path = path
+        .components()
+        .fold(std::path::PathBuf::new(), |mut path, component| {
+            match component {
+                std::path::Component::ParentDir => {
+                    path.pop();
+                }
+                std::path::Component::CurDir => {}
+                component => path.push(component),
+            }
+            path
+        });
+    Utf8PathBuf::from_path_buf(path)
+        .map_err(|p| anyhow::anyhow!("Path {} is not valid UTF-8", p.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_args() -> cli::Build {
+        cli::Build {
+            features: None,
+            all_features: false,
+            no_default_features: false,
+            target_dir: None,
+            manifest_path: None,
+            package: None,
+            docker_image: cli::DEFAULT_BUILDER_IMAGE.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_target_dir_outside_workspace_is_mounted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = workspace_mounts(
+            Utf8Path::new("/work/project"),
+            Some(&dir.path().join("target")),
+        )
+        .unwrap();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].container, CONTAINER_TARGET_DIR);
+
+        let mounts = workspace_mounts(
+            Utf8Path::new("/work/project"),
+            Some("/work/project/target".as_ref()),
+        )
+        .unwrap();
+        assert_eq!(mounts.len(), 1);
+
+        let mounts = workspace_mounts(
+            Utf8Path::new("/work/project"),
+            Some("/work/project/../project/target".as_ref()),
+        )
+        .unwrap();
+        assert_eq!(mounts.len(), 1);
+
+        // a target dir reached through a symlink into the workspace is inside it
+        let workspace = dir.path().canonicalize().unwrap().join("project");
+        std::fs::create_dir(&workspace).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&workspace, &link).unwrap();
+        let mounts = workspace_mounts(
+            Utf8Path::from_path(&workspace).unwrap(),
+            Some(&link.join("target")),
+        )
+        .unwrap();
+        assert_eq!(mounts.len(), 1);
+    }
+
+    #[test]
+    fn test_flags_are_forwarded_with_container_paths() {
+        let mounts = workspace_mounts(Utf8Path::new("/work/project"), None).unwrap();
+        let args = cli::Build {
+            features: Some("a b".to_string()),
+            no_default_features: true,
+            manifest_path: Some("/work/project/guest/Cargo.toml".into()),
+            package: Some(Some("guest".to_string())),
+            ..build_args()
+        };
+        let cargo = cargo_args(&args, &mounts).unwrap();
+        let expected: Vec<OsString> = [
+            "cargo",
+            "build",
+            "--release",
+            "-Zbuild-std",
+            "--message-format=json-render-diagnostics",
+            "--features",
+            "a b",
+            "--no-default-features",
+            "--manifest-path",
+            "/code/guest/Cargo.toml",
+            "--package",
+            "guest",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        assert_eq!(cargo, expected);
+    }
+
+    #[test]
+    fn test_paths_outside_mounts_are_rejected() {
+        let mounts = workspace_mounts(Utf8Path::new("/work/project"), None).unwrap();
+        let args = cli::Build {
+            manifest_path: Some("/elsewhere/Cargo.toml".into()),
+            ..build_args()
+        };
+        assert!(cargo_args(&args, &mounts).is_err());
+    }
+
+    #[test]
+    fn test_artifact_paths_are_mapped_to_host() {
+        let mounts = workspace_mounts(Utf8Path::new("/work/project"), None).unwrap();
+        let elf = Utf8Path::new("/code/target/mips-unknown-none/release/guest");
+        assert_eq!(
+            mounts[0].to_host(elf).unwrap(),
+            "/work/project/target/mips-unknown-none/release/guest"
+        );
+    }
+}
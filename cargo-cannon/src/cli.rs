@@ -1,6 +1,9 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
+/// Docker image used to cross compile Cannon programs
+pub const DEFAULT_BUILDER_IMAGE: &str = "ghcr.io/badboilabs/cannon-rs/builder:main";
+
 #[derive(Parser)]
 #[command(bin_name = "cargo", version, author)]
 pub enum CargoSubcommand {
@@ -18,8 +21,13 @@ pub enum Cannon {
     /// Create a new Cannon Rust project
     #[command(name = "new", version, author)]
     New(New),
+
+    /// Load a MIPS elf file into the state.json and meta.json files used by the Cannon emulator
+    #[command(name = "load-elf", version, author)]
+    LoadElf(LoadElf),
 }
 
+/// Programs are always built with the release profile using the Cannon-rs builder docker image
 #[derive(Parser, Debug)]
 pub struct Build {
     /// Space-separated list of features to activate
@@ -45,6 +53,10 @@ pub struct Build {
     /// Package to expand
     #[arg(short, long, value_name = "SPEC", num_args = 0..=1)]
     pub package: Option<Option<String>>,
+
+    /// Docker image to build with
+    #[arg(long, value_name = "IMAGE", default_value = DEFAULT_BUILDER_IMAGE)]
+    pub docker_image: String,
 }
 
 #[derive(Parser, Debug)]
@@ -52,3 +64,30 @@ pub struct New {
     /// Path to create the new Cannon project
     pub path: String,
 }
+
+#[derive(Parser, Debug)]
+pub struct LoadElf {
+    /// Path to the MIPS elf file to load
+    #[arg(long, value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Patches to apply to the loaded program
+    #[arg(long, value_name = "PATCH", value_delimiter = ',', default_values = ["go", "stack"])]
+    pub patch: Vec<Patch>,
+
+    /// Output path of the state json. Written gzip compressed if ending in .gz
+    #[arg(long, value_name = "PATH", default_value = "state.json")]
+    pub out: PathBuf,
+
+    /// Output path of the symbol metadata json. Not written if empty
+    #[arg(long, value_name = "PATH", default_value = "meta.json")]
+    pub meta: PathBuf,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Patch {
+    /// Patch out parts of the Go runtime that cannot run in Cannon
+    Go,
+    /// Set up the initial stack
+    Stack,
+}
@@ -1,10 +1,11 @@
 use crate::cli::CargoSubcommand;
+use anyhow::Context;
 use cargo_generate::{generate, GenerateArgs, TemplatePath, Vcs};
 use clap::Parser;
 use io::Write;
-use std::ffi::OsString;
-use std::{env, io, process};
+use std::{io, process};
 
+mod builder;
 mod cli;
 
 fn main() {
@@ -18,10 +19,6 @@ fn main() {
     });
 }
 
-fn cargo_binary() -> OsString {
-    env::var_os("CARGO").unwrap_or_else(|| "cargo".to_owned().into())
-}
-
 fn new(args: cli::New) {
     let cli::New { path } = args;
 
@@ -39,18 +36,34 @@ fn new(args: cli::New) {
     println!("Created new Cannon project at {}", path.display());
 }
 
-fn build(_args: cli::Build) {}
+fn load_elf(args: cli::LoadElf) -> Result<i32, anyhow::Error> {
+    let elf = std::fs::read(&args.path)
+        .with_context(|| format!("Unable to read elf file {}", args.path.display()))?;
+
+    let mut state = cannon_elf::load_elf(&elf)?;
+    for patch in args.patch {
+        match patch {
+            cli::Patch::Go => cannon_elf::patch_go(&elf, &mut state)?,
+            cli::Patch::Stack => cannon_elf::patch_stack(&mut state)?,
+        }
+    }
+    cannon_elf::write_json(&args.out, &state)?;
+
+    if !args.meta.as_os_str().is_empty() {
+        let metadata = cannon_elf::Metadata::from_elf(&elf)?;
+        cannon_elf::write_json(&args.meta, &metadata)?;
+    }
+    Ok(0)
+}
 
 fn cargo_cannon() -> Result<i32, anyhow::Error> {
     let CargoSubcommand::Cannon(args) = CargoSubcommand::parse();
     match args {
-        cli::Cannon::Build(args) => build(args),
-        cli::Cannon::New(args) => new(args),
+        cli::Cannon::Build(args) => builder::build(args),
+        cli::Cannon::New(args) => {
+            new(args);
+            Ok(0)
+        }
+        cli::Cannon::LoadElf(args) => load_elf(args),
     }
-
-    // Cross compile the MIPS32 using our docker image
-
-    // Patch the resulting elf and convert to a Cannon json file
-
-    Ok(0)
 }
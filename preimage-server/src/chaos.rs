@@ -0,0 +1,266 @@
+//! Fault injection to exercise how guests handle a misbehaving host
+//!
+//! The emulator may transfer only a few bytes of a request or response at a time, and a host may be slow or
+//! serve bad data. [`Fragmented`] splits every read and write into small chunks and [`ChaosProvider`] delays
+//! responses and truncates or corrupts the preimages of chosen keys. All choices are drawn from a seeded
+//! generator so a failing run can be reproduced exactly.
+
+use crate::preimage_provider::{AsyncPreimageProvider, PreimageStream};
+use async_trait::async_trait;
+use log::warn;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+/// xorshift64, which is plenty for picking chunk sizes and delays
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number between 1 and `max` inclusive
+    fn between_one_and(&mut self, max: u64) -> u64 {
+        1 + self.next() % max.max(1)
+    }
+}
+
+/// Wraps a stream so that every read and write transfers a pseudo-random number of bytes between 1 and `max`
+pub struct Fragmented<T> {
+    inner: T,
+    rng: Rng,
+    max: usize,
+}
+
+impl<T> Fragmented<T> {
+    pub fn new(inner: T, seed: u64, max: usize) -> Self {
+        Self {
+            inner,
+            rng: Rng::new(seed),
+            max,
+        }
+    }
+
+    fn next_len(&mut self, available: usize) -> usize {
+        available.min(self.rng.between_one_and(self.max as u64) as usize)
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Fragmented<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = self.next_len(buf.remaining());
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(n));
+        let result = Pin::new(&mut self.inner).poll_read(cx, &mut limited);
+        let filled = limited.filled().len();
+        buf.advance(filled);
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Fragmented<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = self.next_len(buf.len());
+        Pin::new(&mut self.inner).poll_write(cx, &buf[..n])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Delays every response and serves bad preimages for chosen keys
+///
+/// A truncated preimage is announced with its full length but only the first half is sent, after which the server
+/// stops as it would if the host failed part way through a response. [`AsyncPreimageProvider::get`] has no length
+/// separate from the data to announce, so it returns `None` for truncated keys rather than a shorter preimage. A
+/// corrupted preimage has one byte changed.
+///
+/// # Examples
+/// ```
+/// use preimage_server::chaos::ChaosProvider;
+/// use std::collections::HashMap;
+/// use std::time::Duration;
+///
+/// let preimages = HashMap::from([([2; 32], b"data".to_vec())]);
+/// let provider = ChaosProvider::new(preimages, 1)
+///     .with_max_delay(Duration::from_millis(5))
+///     .with_corrupted([[2; 32]]);
+/// ```
+pub struct ChaosProvider<P> {
+    inner: P,
+    rng: RefCell<Rng>,
+    max_delay: Duration,
+    truncated: HashSet<[u8; 32]>,
+    corrupted: HashSet<[u8; 32]>,
+}
+
+impl<P: AsyncPreimageProvider> ChaosProvider<P> {
+    pub fn new(inner: P, seed: u64) -> Self {
+        Self {
+            inner,
+            rng: RefCell::new(Rng::new(seed)),
+            max_delay: Duration::ZERO,
+            truncated: HashSet::new(),
+            corrupted: HashSet::new(),
+        }
+    }
+
+    /// Wait a pseudo-random time up to `max_delay` before every response
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_truncated(mut self, keys: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.truncated.extend(keys);
+        self
+    }
+
+    pub fn with_corrupted(mut self, keys: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.corrupted.extend(keys);
+        self
+    }
+
+    async fn delay(&self) {
+        let max = self.max_delay.as_micros() as u64;
+        if max > 0 {
+            let delay = self.rng.borrow_mut().next() % (max + 1);
+            tokio::time::sleep(Duration::from_micros(delay)).await;
+        }
+    }
+
+    fn corrupt(&self, key: &[u8; 32], data: &mut [u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut rng = self.rng.borrow_mut();
+        let index = rng.next() as usize % data.len();
+        // never zero so the byte always changes
+        data[index] ^= rng.between_one_and(255) as u8;
+        warn!(
+            "Corrupted preimage 0x{} at byte {}",
+            hex::encode(key),
+            index
+        );
+    }
+}
+
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider> AsyncPreimageProvider for ChaosProvider<P> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        self.delay().await;
+        let mut data = self.inner.get(key).await?;
+        if self.truncated.contains(key) {
+            warn!(
+                "Failing to serve truncated preimage 0x{} as a whole value",
+                hex::encode(key)
+            );
+            return None;
+        }
+        if self.corrupted.contains(key) {
+            self.corrupt(key, &mut data);
+        }
+        Some(data)
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        self.delay().await;
+        let mut preimage = self.inner.open(key).await?;
+        if self.corrupted.contains(key) {
+            let mut data = Vec::new();
+            if let Err(e) = preimage.reader.read_to_end(&mut data).await {
+                warn!("Unable to read preimage 0x{}: {}", hex::encode(key), e);
+                return None;
+            }
+            self.corrupt(key, &mut data);
+            preimage = data.into();
+        }
+        if self.truncated.contains(key) {
+            warn!(
+                "Truncating preimage 0x{} to {} of {} bytes",
+                hex::encode(key),
+                preimage.len / 2,
+                preimage.len
+            );
+            preimage.reader = Box::new(preimage.reader.take(preimage.len / 2));
+        }
+        Some(preimage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    async fn read_all(provider: &impl AsyncPreimageProvider, key: &[u8; 32]) -> (u64, Vec<u8>) {
+        let mut preimage = provider.open(key).await.unwrap();
+        let mut data = Vec::new();
+        preimage.reader.read_to_end(&mut data).await.unwrap();
+        (preimage.len, data)
+    }
+
+    #[tokio::test]
+    async fn test_truncated_and_corrupted_preimages() {
+        let data: Vec<u8> = (0..100).collect();
+        let preimages = HashMap::from([
+            ([1; 32], data.clone()),
+            ([2; 32], data.clone()),
+            ([3; 32], data.clone()),
+        ]);
+        let provider = ChaosProvider::new(preimages, 42)
+            .with_max_delay(Duration::from_millis(1))
+            .with_truncated([[2; 32]])
+            .with_corrupted([[3; 32]]);
+
+        assert_eq!(read_all(&provider, &[1; 32]).await, (100, data.clone()));
+        assert_eq!(
+            read_all(&provider, &[2; 32]).await,
+            (100, data[..50].to_vec())
+        );
+
+        let (len, corrupted) = read_all(&provider, &[3; 32]).await;
+        assert_eq!(len, 100);
+        let changed = corrupted.iter().zip(&data).filter(|(a, b)| a != b).count();
+        assert_eq!(changed, 1);
+
+        // the same seed always makes the same choices
+        let again = ChaosProvider::new(HashMap::from([([3; 32], data.clone())]), 42)
+            .with_max_delay(Duration::from_millis(1))
+            .with_corrupted([[3; 32]]);
+        // the first two responses each drew a delay
+        again.delay().await;
+        again.delay().await;
+        assert_eq!(read_all(&again, &[3; 32]).await, (100, corrupted));
+
+        // a whole value cannot be announced with its full length, so it is not served at all
+        assert_eq!(provider.get(&[2; 32]).await, None);
+        assert_eq!(provider.get(&[1; 32]).await, Some(data));
+    }
+}
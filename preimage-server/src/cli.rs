@@ -1,10 +1,214 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use preimage_server::listen::ListenAddr;
+use preimage_server::local_keys;
+use preimage_server::store::{self, Format};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Path to pre-image json file to load or directory to scan
-    pub path: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to pre-image json file to load, directory to scan or preimage archive to serve from. Can be left out
+    /// when serving only `--raw` data
+    #[arg(required_unless_present = "raw")]
+    pub path: Option<PathBuf>,
+
+    /// Format of the store at PATH: json, dir, disk-kv or archive. Detected from its contents otherwise, which
+    /// needs a directory to already contain preimages
+    #[arg(long, value_name = "FORMAT")]
+    pub format: Option<Format>,
+
+    /// Data file, or directory of data files, to serve under both the keccak256 and sha256 keys of their contents.
+    /// Can be given multiple times
+    #[arg(long, value_name = "PATH")]
+    pub raw: Vec<PathBuf>,
+
+    /// Serve a per-run input under a local key. VALUE is `0x` prefixed hex, a decimal integer (as a big-endian u64)
+    /// or `@<path>` for the contents of a file. Can be given multiple times and overrides `--local-config`
+    #[arg(long = "local", value_name = "INDEX=VALUE", value_parser = local_keys::parse_assignment)]
+    pub local: Vec<([u8; 32], Vec<u8>)>,
+
+    /// TOML file with a `[local]` table mapping local key indices to values in the same formats as `--local`
+    #[arg(long, value_name = "PATH")]
+    pub local_config: Option<PathBuf>,
+
+    /// Pre-image json file or directory consulted for keys that are not found in the main preimages
+    #[arg(long, value_name = "PATH")]
+    pub fallback: Option<PathBuf>,
+
+    /// op-program disk key-value store directory consulted for keys that are not found in the preimages loaded
+    /// at startup. Files are read as they are requested
+    #[arg(long, value_name = "DIR")]
+    pub disk_kv: Option<PathBuf>,
+
+    /// Execution client JSON-RPC endpoint used to retrieve hinted keccak256 preimages that are not found locally
+    #[arg(long, value_name = "URL")]
+    pub rpc_url: Option<String>,
+
+    /// Directory to save preimages retrieved over RPC in. It can be passed as the preimage path on later runs
+    #[arg(long, value_name = "DIR", requires = "rpc_url")]
+    pub rpc_cache_dir: Option<PathBuf>,
+
+    /// Beacon API endpoint used to retrieve hinted beacon blocks and states, served as sha256 preimages
+    #[arg(long, value_name = "URL")]
+    pub beacon_url: Option<String>,
+
+    /// Check that every keccak256 and sha256 preimage matches its key. Preimages loaded from files are checked
+    /// at startup and all mismatches reported. Preimages retrieved later are checked as they are served
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Write every hint received and preimage requested, in order and with their sizes, to this JSON file on shutdown
+    #[arg(long, value_name = "PATH")]
+    pub record_log: Option<PathBuf>,
+
+    /// Write the preimages the guest requested to this path on shutdown. Loading it replays the run
+    #[arg(long, value_name = "PATH")]
+    pub export: Option<PathBuf>,
+
+    /// Format used by `--export`: json, dir, disk-kv (loaded with `--disk-kv`) or archive
+    #[arg(long, value_name = "FORMAT", default_value_t = Format::Json, requires = "export")]
+    pub export_format: Format,
+
+    /// Serve preimage requests to clients connecting to `tcp://<host>:<port>` or `unix://<path>` instead of over the
+    /// inherited file descriptors. Any number of clients can connect at once
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<ListenAddr>,
+
+    /// Receive hints from clients connecting to this address when using `--listen`
+    #[arg(long, value_name = "ADDR", requires = "listen")]
+    pub hint_listen: Option<ListenAddr>,
+
+    /// Write a JSON summary of the requests and hints of the run to this file on shutdown
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// How often request statistics are logged at debug level
+    #[arg(long, value_name = "SECS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_interval: u64,
+
+    /// Inject faults to test how the guest handles a misbehaving host. Every read and write is split into small
+    /// chunks and responses are delayed
+    #[arg(long)]
+    pub chaos: bool,
+
+    /// Seed for the choices made by `--chaos`. The same seed makes the same choices
+    #[arg(long, value_name = "SEED", default_value_t = 1, requires = "chaos")]
+    pub chaos_seed: u64,
+
+    /// Largest number of bytes transferred by a single read or write with `--chaos`
+    #[arg(long, value_name = "BYTES", default_value_t = 7, requires = "chaos")]
+    pub chaos_max_chunk: usize,
+
+    /// Longest delay before a response with `--chaos`
+    #[arg(long, value_name = "MS", default_value_t = 10, requires = "chaos")]
+    pub chaos_max_delay_ms: u64,
+
+    /// Announce the full length of this key's preimage but only send half of it, then stop. Can be given multiple
+    /// times
+    #[arg(long, value_name = "KEY", value_parser = store::parse_key, requires = "chaos")]
+    pub chaos_truncate: Vec<[u8; 32]>,
+
+    /// Change one byte of this key's preimage. Can be given multiple times
+    #[arg(long, value_name = "KEY", value_parser = store::parse_key, requires = "chaos")]
+    pub chaos_corrupt: Vec<[u8; 32]>,
+
+    /// What to do when the guest requests a preimage that cannot be found. With `--listen` the connection that
+    /// requested it is closed instead
+    #[arg(long, value_enum, default_value_t = OnMissing::Fail)]
+    pub on_missing: OnMissing,
+
+    /// Exit code used with `--on-missing exit`
+    #[arg(long, value_name = "CODE", default_value_t = 3)]
+    pub missing_exit_code: i32,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print the keys that data files are served under with `--raw`
+    Keys {
+        /// Data files or directories of data files
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Add data files and local inputs to a store, creating it if it does not exist. The keys they are added under
+    /// are printed
+    Add {
+        /// Store to add to
+        store: PathBuf,
+        /// Data files to add under the keys computed from their contents
+        #[arg(required_unless_present = "local")]
+        files: Vec<PathBuf>,
+        /// Which keys to add data files under
+        #[arg(long, value_enum, default_value_t = AddKeyType::Both)]
+        key_type: AddKeyType,
+        /// Add a local input in the same format as the server's `--local`. Can be given multiple times
+        #[arg(long = "local", value_name = "INDEX=VALUE", value_parser = local_keys::parse_assignment)]
+        local: Vec<([u8; 32], Vec<u8>)>,
+        #[command(flatten)]
+        output_args: OutputArgs,
+    },
+    /// Print the preimage of a key
+    Get {
+        store: PathBuf,
+        #[arg(value_parser = store::parse_key)]
+        key: [u8; 32],
+        /// Print the preimage as `0x` prefixed hex rather than raw bytes
+        #[arg(long)]
+        hex: bool,
+    },
+    /// List every key in a store with its key type and preimage length
+    List { store: PathBuf },
+    /// Check that every keccak256 and sha256 preimage in the stores matches its key
+    Verify {
+        #[arg(required = true)]
+        stores: Vec<PathBuf>,
+    },
+    /// Combine stores into one. Stores with different preimages for the same key are an error. Writing an archive
+    /// creates a single indexed file that is served without loading it into memory
+    Merge {
+        /// Store to write
+        output: PathBuf,
+        /// Stores to combine
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        #[command(flatten)]
+        output_args: OutputArgs,
+    },
+    /// Write a store in another format
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        #[command(flatten)]
+        output_args: OutputArgs,
+    },
+}
+
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Format of the store to write: json, dir, disk-kv or archive. Inferred from an existing store or the
+    /// extension, `.json` or `.bin` for an archive, otherwise
+    #[arg(long, value_name = "FORMAT")]
+    pub format: Option<Format>,
+    /// Compress every preimage with LZ4 when writing an archive
+    #[arg(long)]
+    pub compress: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddKeyType {
+    Keccak256,
+    Sha256,
+    Both,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnMissing {
+    /// Log an error naming the key and stop the server with an error
+    Fail,
+    /// Log an error naming the key and exit with `--missing-exit-code`
+    Exit,
 }
@@ -0,0 +1,246 @@
+//! Subcommands that manage preimage stores rather than serving them
+
+use crate::cli::{AddKeyType, OutputArgs};
+use anyhow::{bail, Context, Result};
+use preimage_server::error::key_type_name;
+use preimage_server::preimage_provider::{
+    ArchiveProvider, AsyncPreimageProvider, DirectoryProvider, DiskKvProvider,
+};
+use preimage_server::raw;
+use preimage_server::store::{self, Format, Preimages};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub fn add(
+    path: &Path,
+    files: &[PathBuf],
+    key_type: AddKeyType,
+    local: &[([u8; 32], Vec<u8>)],
+    output_args: &OutputArgs,
+) -> Result<()> {
+    let mut preimages = if path.exists() {
+        store::load(path)?
+    } else {
+        Preimages::new()
+    };
+    for file in files {
+        for preimage in raw::load(file)? {
+            if key_type != AddKeyType::Sha256 {
+                println!(
+                    "{}: 0x{}",
+                    preimage.path.display(),
+                    hex::encode(preimage.keccak256_key)
+                );
+                preimages.insert(preimage.keccak256_key, preimage.data.clone());
+            }
+            if key_type != AddKeyType::Keccak256 {
+                println!(
+                    "{}: 0x{}",
+                    preimage.path.display(),
+                    hex::encode(preimage.sha256_key)
+                );
+                preimages.insert(preimage.sha256_key, preimage.data);
+            }
+        }
+    }
+    for (key, value) in local {
+        println!("local: 0x{}", hex::encode(key));
+        preimages.insert(*key, value.clone());
+    }
+    save(path, &preimages, output_args)
+}
+
+pub async fn get(path: &Path, key: &[u8; 32], as_hex: bool) -> Result<()> {
+    // only the requested preimage is read from stores that can be large
+    let value = match Format::detect(path)? {
+        Some(Format::Json) => store::load(path)?.remove(key),
+        Some(Format::Directory) => DirectoryProvider::new(path).get(key).await,
+        Some(Format::DiskKv) => DiskKvProvider::new(path).get(key).await,
+        Some(Format::Archive) => {
+            AsyncPreimageProvider::get(&ArchiveProvider::open(path)?, key).await
+        }
+        None => None,
+    };
+    let Some(value) = value else {
+        bail!(
+            "No preimage for key 0x{} in {}",
+            hex::encode(key),
+            path.display()
+        );
+    };
+    if as_hex {
+        println!("0x{}", hex::encode(value));
+    } else {
+        std::io::stdout().write_all(&value)?;
+    }
+    Ok(())
+}
+
+pub fn list(path: &Path) -> Result<()> {
+    for (key, len) in store::list(path)? {
+        println!("0x{} {} {}", hex::encode(key), key_type_name(key[0]), len);
+    }
+    Ok(())
+}
+
+pub fn verify(paths: &[PathBuf]) -> Result<()> {
+    let stores = paths
+        .iter()
+        .map(|path| store::load(path))
+        .collect::<Result<Vec<_>>>()?;
+    crate::verify_at_load(&stores.iter().collect::<Vec<_>>())?;
+    println!(
+        "All {} preimages match their keys",
+        stores.iter().map(Preimages::len).sum::<usize>()
+    );
+    Ok(())
+}
+
+pub fn merge(output: &Path, inputs: &[PathBuf], output_args: &OutputArgs) -> Result<()> {
+    let mut merged = Preimages::new();
+    for input in inputs {
+        for (key, value) in store::load(input)? {
+            match merged.get(&key) {
+                Some(existing) if *existing != value => bail!(
+                    "{} has a different preimage for key 0x{}",
+                    input.display(),
+                    hex::encode(key)
+                ),
+                _ => {
+                    merged.insert(key, value);
+                }
+            }
+        }
+    }
+    save(output, &merged, output_args)
+}
+
+pub fn convert(input: &Path, output: &Path, output_args: &OutputArgs) -> Result<()> {
+    save(output, &store::load(input)?, output_args)
+}
+
+fn save(path: &Path, preimages: &Preimages, output_args: &OutputArgs) -> Result<()> {
+    let format = match output_args.format {
+        Some(format) => format,
+        None => Format::infer(path)?,
+    };
+    store::save(path, format, preimages, output_args.compress)
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    eprintln!(
+        "Wrote {} preimages to {} ({})",
+        preimages.len(),
+        path.display(),
+        format
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cannon_io::oracle::KeyType;
+
+    fn output_args(format: Format) -> OutputArgs {
+        OutputArgs {
+            format: Some(format),
+            compress: false,
+        }
+    }
+
+    #[test]
+    fn test_add_with_key_type() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data.bin"), b"data").unwrap();
+        let files = [dir.path().join("data.bin")];
+
+        for (key_type, expected) in [
+            (AddKeyType::Keccak256, vec![KeyType::Keccak256 as u8]),
+            (AddKeyType::Sha256, vec![KeyType::Sha256 as u8]),
+            (
+                AddKeyType::Both,
+                vec![KeyType::Keccak256 as u8, KeyType::Sha256 as u8],
+            ),
+        ] {
+            let path = dir.path().join(format!("{:?}.json", key_type));
+            add(&path, &files, key_type, &[], &output_args(Format::Json)).unwrap();
+            let preimages = store::load(&path).unwrap();
+            let mut key_types: Vec<u8> = preimages.keys().map(|key| key[0]).collect();
+            key_types.sort();
+            assert_eq!(key_types, expected, "{:?}", key_type);
+            assert!(preimages.values().all(|value| value == b"data"));
+        }
+    }
+
+    #[test]
+    fn test_merge_rejects_conflicting_preimages() {
+        let dir = tempfile::tempdir().unwrap();
+        let one = dir.path().join("one.json");
+        let two = dir.path().join("two.json");
+        let three = dir.path().join("three.json");
+        let json = Format::Json;
+        store::save(
+            &one,
+            json,
+            &Preimages::from([([1; 32], b"a".to_vec())]),
+            false,
+        )
+        .unwrap();
+        store::save(
+            &two,
+            json,
+            &Preimages::from([([1; 32], b"a".to_vec()), ([2; 32], b"b".to_vec())]),
+            false,
+        )
+        .unwrap();
+        store::save(
+            &three,
+            json,
+            &Preimages::from([([2; 32], b"c".to_vec())]),
+            false,
+        )
+        .unwrap();
+
+        let merged = dir.path().join("merged.bin");
+        merge(
+            &merged,
+            &[one.clone(), two.clone()],
+            &output_args(Format::Archive),
+        )
+        .unwrap();
+        assert_eq!(store::load(&merged).unwrap().len(), 2);
+
+        let e = merge(
+            &dir.path().join("conflict.json"),
+            &[two, three.clone()],
+            &output_args(json),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains(&three.display().to_string()));
+        assert!(!dir.path().join("conflict.json").exists());
+    }
+
+    #[test]
+    fn test_convert_between_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let preimages = Preimages::from([([1; 32], b"one".to_vec()), ([2; 32], Vec::new())]);
+        let json = dir.path().join("preimages.json");
+        store::save(&json, Format::Json, &preimages, false).unwrap();
+
+        let archive = dir.path().join("preimages.bin");
+        convert(
+            &json,
+            &archive,
+            &OutputArgs {
+                format: None,
+                compress: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(Format::detect(&archive).unwrap(), Some(Format::Archive));
+
+        let disk_kv = dir.path().join("kv");
+        convert(&archive, &disk_kv, &output_args(Format::DiskKv)).unwrap();
+        assert_eq!(Format::detect(&disk_kv).unwrap(), Some(Format::DiskKv));
+        assert_eq!(store::load(&disk_kv).unwrap(), preimages);
+    }
+}
@@ -0,0 +1,44 @@
+use cannon_io::oracle::KeyType;
+use std::fmt;
+
+/// The guest requested a preimage that none of the providers could serve
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPreimageError {
+    pub key: [u8; 32],
+}
+
+impl fmt::Display for MissingPreimageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Guest requested preimage that does not exist. Key: 0x{}, key type: {}",
+            hex::encode(self.key),
+            key_type_name(self.key[0])
+        )
+    }
+}
+
+impl std::error::Error for MissingPreimageError {}
+
+/// Human readable name of the type byte of a key
+pub fn key_type_name(type_byte: u8) -> String {
+    match KeyType::try_from(type_byte) {
+        Ok(key_type) => format!("{:?}", key_type),
+        Err(unknown) => format!("unknown (0x{:02x})", unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_names_key_and_type() {
+        let mut key = [0xab; 32];
+        key[0] = 2;
+        let message = MissingPreimageError { key }.to_string();
+        assert!(message.contains(&format!("0x02{}", "ab".repeat(31))));
+        assert!(message.contains("key type: Keccak256"));
+        assert_eq!(key_type_name(0x42), "unknown (0x42)");
+    }
+}
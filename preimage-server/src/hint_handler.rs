@@ -0,0 +1,106 @@
+use crate::preimage_provider::PreimageStore;
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::cell::RefCell;
+
+/// A hint in the `<type> <0x prefixed hex data>` format used by the fault proof program
+/// e.g. `l1-block-header 0x0102...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint {
+    pub hint_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Hint {
+    /// Decode a hint as received on the hint channel (without its length prefix)
+    pub fn decode(hint: &[u8]) -> Result<Self> {
+        let hint = std::str::from_utf8(hint)?;
+        let (hint_type, data) = hint
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Hint is not of the form `<type> <data>`: {}", hint))?;
+        let data = hex::decode(data.trim_start_matches("0x"))?;
+        Ok(Self {
+            hint_type: hint_type.to_string(),
+            data,
+        })
+    }
+}
+
+/// Receives each hint sent by the guest before it is acknowledged
+///
+/// The guest waits for the acknowledgement before requesting any preimages the hint refers to.
+/// Handlers can therefore fetch those preimages and insert them into the store so they are available
+/// when requested, rather than all preimages having to be provided up front.
+pub trait HintHandler {
+    fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()>;
+
+    /// Receives every hint exactly as it was sent, without its length prefix, before it is decoded. Hints that
+    /// cannot be decoded are only seen here
+    fn handle_raw_hint(&mut self, _hint: &[u8]) {}
+}
+
+impl<H: HintHandler + ?Sized> HintHandler for Box<H> {
+    fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()> {
+        (**self).handle_hint(hint, preimages)
+    }
+
+    fn handle_raw_hint(&mut self, hint: &[u8]) {
+        (**self).handle_raw_hint(hint)
+    }
+}
+
+/// Allows one handler to be shared by several hint channels, e.g. connections to a listening server
+impl<H: HintHandler + ?Sized> HintHandler for &RefCell<H> {
+    fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()> {
+        self.borrow_mut().handle_hint(hint, preimages)
+    }
+
+    fn handle_raw_hint(&mut self, hint: &[u8]) {
+        self.borrow_mut().handle_raw_hint(hint)
+    }
+}
+
+/// Every handler receives each hint in turn
+impl<H: HintHandler> HintHandler for Vec<H> {
+    fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()> {
+        self.iter_mut()
+            .try_for_each(|handler| handler.handle_hint(hint, preimages))
+    }
+
+    fn handle_raw_hint(&mut self, hint: &[u8]) {
+        self.iter_mut()
+            .for_each(|handler| handler.handle_raw_hint(hint))
+    }
+}
+
+/// Default hint handler which only logs the hints it receives
+pub struct LogHintHandler;
+
+impl HintHandler for LogHintHandler {
+    fn handle_hint(&mut self, hint: &Hint, _preimages: &mut dyn PreimageStore) -> Result<()> {
+        debug!(
+            "Received hint: {} 0x{}",
+            hint.hint_type,
+            hex::encode(&hint.data)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Hint::decode(b"l1-block-header 0xabcd").unwrap(),
+            Hint {
+                hint_type: "l1-block-header".to_string(),
+                data: vec![0xab, 0xcd]
+            }
+        );
+        assert!(Hint::decode(b"no-data").is_err());
+        assert!(Hint::decode(b"l1-block-header 0xnothex").is_err());
+    }
+}
@@ -0,0 +1,39 @@
+//! The Cannon preimage oracle protocol for hosts
+//!
+//! The Cannon emulator forwards the hints and preimage requests made by the guest to a child process over
+//! pipes. [`serve`] and [`serve_hints`] implement the host side of these protocols over any async reader and
+//! writer so they can be embedded in other hosts and test harnesses as well as the `preimage-server` binary.
+//!
+//! # Examples
+//! ```no_run
+//! use preimage_server::hint_handler::LogHintHandler;
+//! use std::cell::RefCell;
+//! use std::collections::HashMap;
+//! use std::os::fd::FromRawFd;
+//! use tokio::fs::File;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let preimages = RefCell::new(HashMap::<[u8; 32], Vec<u8>>::new());
+//! let (hint_reader, hint_writer) = unsafe { (File::from_raw_fd(3), File::from_raw_fd(4)) };
+//! let (reader, writer) = unsafe { (File::from_raw_fd(5), File::from_raw_fd(6)) };
+//! tokio::try_join!(
+//!     preimage_server::serve_hints(hint_reader, hint_writer, LogHintHandler, &preimages),
+//!     preimage_server::serve(reader, writer, &preimages),
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod chaos;
+pub mod error;
+pub mod hint_handler;
+pub mod listen;
+pub mod local_keys;
+pub mod preimage_provider;
+pub mod raw;
+pub mod recording;
+mod server;
+pub mod stats;
+pub mod store;
+
+pub use server::{serve, serve_hints};
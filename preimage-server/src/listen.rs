@@ -0,0 +1,236 @@
+//! Serve the preimage and hint protocols to clients connecting over TCP or Unix sockets
+//!
+//! Each connection carries a single channel using exactly the same protocol as the inherited file descriptors.
+//! Any number of clients can be connected at once and are served concurrently on the current task, so they
+//! share the same providers and preimage store.
+//!
+//! # Examples
+//! ```no_run
+//! use preimage_server::listen::{serve_connections, Listener};
+//! use std::collections::HashMap;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let preimages = HashMap::<[u8; 32], Vec<u8>>::new();
+//! let listener = Listener::bind(&"tcp://127.0.0.1:9000".parse()?).await?;
+//! serve_connections(listener, |connection| {
+//!     let (reader, writer) = tokio::io::split(connection);
+//!     preimage_server::serve(reader, writer, &preimages)
+//! })
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{anyhow, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, error, info, warn};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::time::Instant;
+
+/// How long to stop accepting after running out of a resource such as file descriptors, giving connections time
+/// to close and free it
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// An address to listen on, given as `tcp://<host>:<port>` or `unix://<path>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(Self::Tcp(addr.to_string()))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            Ok(Self::Unix(path.into()))
+        } else {
+            Err(anyhow!(
+                "Expected tcp://<host>:<port> or unix://<path>, got {}",
+                s
+            ))
+        }
+    }
+}
+
+/// A stream accepted from a [`Listener`]
+pub trait Connection: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Connection for T {}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Bind to `addr`. A socket file left at a Unix address by an earlier server is replaced
+    pub async fn bind(addr: &ListenAddr) -> Result<Self> {
+        let listener = match addr {
+            ListenAddr::Tcp(addr) => Self::Tcp(TcpListener::bind(addr).await?),
+            ListenAddr::Unix(path) => {
+                if std::fs::metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                Self::Unix(UnixListener::bind(path)?)
+            }
+        };
+        info!("Listening on {:?}", addr);
+        Ok(listener)
+    }
+
+    async fn accept(&self) -> std::io::Result<Box<dyn Connection>> {
+        Ok(match self {
+            Self::Tcp(listener) => Box::new(listener.accept().await?.0),
+            Self::Unix(listener) => Box::new(listener.accept().await?.0),
+        })
+    }
+}
+
+/// Accept connections forever, serving each with the future returned by `serve_connection`
+///
+/// A connection that ends with an error is logged and closed without affecting the others. Errors accepting a
+/// connection are logged and accepting resumes, after a short backoff if the server has run out of a resource.
+/// Only a listener that can no longer accept connections stops the server.
+pub async fn serve_connections<F: Future<Output = Result<()>>>(
+    listener: Listener,
+    mut serve_connection: impl FnMut(Box<dyn Connection>) -> F,
+) -> Result<()> {
+    // only the connections that have been woken are polled
+    let mut connections = FuturesUnordered::new();
+    // existing connections are still served while backing off
+    let mut resume_accepting: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            connection = listener.accept(), if resume_accepting.is_none() => match connection {
+                Ok(connection) => {
+                    debug!("Accepted connection");
+                    connections.push(serve_connection(connection));
+                }
+                Err(e) => match accept_retry_delay(&e) {
+                    Some(delay) => {
+                        warn!("Unable to accept connection: {}", e);
+                        if !delay.is_zero() {
+                            resume_accepting = Some(Instant::now() + delay);
+                        }
+                    }
+                    None => return Err(e.into()),
+                },
+            },
+            _ = tokio::time::sleep_until(resume_accepting.unwrap_or_else(Instant::now)),
+                if resume_accepting.is_some() => resume_accepting = None,
+            Some(result) = connections.next() => match result {
+                Ok(()) => debug!("Connection closed"),
+                Err(e) => error!("Closing connection: {}", e),
+            },
+        }
+    }
+}
+
+/// How long to wait before accepting again after `e`, or `None` if the listener cannot recover from it
+fn accept_retry_delay(e: &std::io::Error) -> Option<Duration> {
+    match e.kind() {
+        // only the connection being accepted failed, e.g. the client went away first
+        ErrorKind::ConnectionAborted
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionRefused
+        | ErrorKind::Interrupted => Some(Duration::ZERO),
+        // the socket is not listening
+        ErrorKind::InvalidInput => None,
+        // most likely out of file descriptors or memory, which is freed as connections close
+        _ => Some(ACCEPT_BACKOFF),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(
+            "tcp://127.0.0.1:9000".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp("127.0.0.1:9000".to_string())
+        );
+        assert_eq!(
+            "unix:///tmp/preimages.sock".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Unix("/tmp/preimages.sock".into())
+        );
+        assert!("127.0.0.1:9000".parse::<ListenAddr>().is_err());
+    }
+
+    #[test]
+    fn test_accept_errors_are_retried_unless_unrecoverable() {
+        use std::io::Error;
+
+        assert_eq!(
+            accept_retry_delay(&Error::from(ErrorKind::ConnectionAborted)),
+            Some(Duration::ZERO)
+        );
+        // EMFILE, too many open files
+        assert_eq!(
+            accept_retry_delay(&Error::from_raw_os_error(24)),
+            Some(ACCEPT_BACKOFF)
+        );
+        assert_eq!(
+            accept_retry_delay(&Error::from(ErrorKind::InvalidInput)),
+            None
+        );
+    }
+
+    async fn request(path: &std::path::Path, key: [u8; 32]) -> Vec<u8> {
+        let mut client = UnixStream::connect(path).await.unwrap();
+        client.write_all(&key).await.unwrap();
+        let mut length = [0; 8];
+        client.read_exact(&mut length).await.unwrap();
+        let mut data = vec![0; u64::from_be_bytes(length) as usize];
+        client.read_exact(&mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_clients_are_served_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preimage-server.sock");
+        let preimages = HashMap::from([([1; 32], b"one".to_vec()), ([2; 32], b"two".to_vec())]);
+        let listener = Listener::bind(&ListenAddr::Unix(path.clone()))
+            .await
+            .unwrap();
+
+        // a client that stays connected does not block the others
+        let idle = UnixStream::connect(&path).await.unwrap();
+        let clients = async {
+            let (one, two) = tokio::join!(request(&path, [1; 32]), request(&path, [2; 32]));
+            // a client requesting a missing key is disconnected without stopping the server
+            let mut missing = UnixStream::connect(&path).await.unwrap();
+            missing.write_all(&[3; 32]).await.unwrap();
+            assert_eq!(missing.read(&mut [0; 8]).await.unwrap(), 0);
+            (one, two, request(&path, [1; 32]).await)
+        };
+
+        tokio::select! {
+            result = serve_connections(listener, |connection| {
+                let (reader, writer) = tokio::io::split(connection);
+                crate::serve(reader, writer, &preimages)
+            }) => panic!("server stopped: {:?}", result),
+            (one, two, again) = clients => {
+                assert_eq!(one, b"one");
+                assert_eq!(two, b"two");
+                assert_eq!(again, b"one");
+            }
+        }
+        drop(idle);
+    }
+}
@@ -0,0 +1,127 @@
+//! Per-run inputs served under local keys
+//!
+//! Local keys are built from an index the same way as `PreimageKey::new_local(&index.to_be_bytes())` in the guest.
+//! Values can be given as
+//! - `0x` prefixed hex bytes
+//! - a decimal integer, served as a big-endian u64
+//! - `@<path>` to serve the contents of a file
+//!
+//! They can be passed on the command line as `INDEX=VALUE` or in a TOML file with a `[local]` table, e.g.
+//! ```toml
+//! [local]
+//! 1 = "0xdeadbeef"
+//! 2 = 10
+//! 3 = "@input.bin"
+//! ```
+//! Paths in a TOML file are relative to the directory containing it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use cannon_io::oracle::PreimageKey;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The key for the local input with the given index
+pub fn local_key(index: u64) -> [u8; 32] {
+    PreimageKey::new_local(&index.to_be_bytes()).into()
+}
+
+/// Parse an `INDEX=VALUE` assignment, reading any file relative to the working directory
+pub fn parse_assignment(assignment: &str) -> Result<([u8; 32], Vec<u8>)> {
+    let (index, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected INDEX=VALUE, got {}", assignment))?;
+    Ok((
+        local_key(parse_index(index)?),
+        parse_value(value, Path::new("."))?,
+    ))
+}
+
+/// Load all local inputs from the `[local]` table of a TOML file
+pub fn from_toml(path: &Path) -> Result<HashMap<[u8; 32], Vec<u8>>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    let config: toml::Table = contents.parse()?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut inputs = HashMap::new();
+    let Some(local) = config.get("local") else {
+        return Ok(inputs);
+    };
+    let local = local
+        .as_table()
+        .ok_or_else(|| anyhow!("`local` must be a table"))?;
+    for (index, value) in local {
+        let value = match value {
+            toml::Value::Integer(n) => u64::try_from(*n)?.to_be_bytes().to_vec(),
+            toml::Value::String(s) => parse_value(s, base_dir)?,
+            other => bail!("Unsupported value for local key {}: {}", index, other),
+        };
+        inputs.insert(local_key(parse_index(index)?), value);
+    }
+    Ok(inputs)
+}
+
+/// Parse a decimal or `0x` prefixed hex index
+fn parse_index(index: &str) -> Result<u64> {
+    let index = index.trim();
+    let parsed = match index.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => index.parse(),
+    };
+    parsed.with_context(|| format!("Invalid local key index {}", index))
+}
+
+fn parse_value(value: &str, base_dir: &Path) -> Result<Vec<u8>> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        Ok(hex::decode(hex)?)
+    } else if let Some(path) = value.strip_prefix('@') {
+        let path = base_dir.join(path);
+        std::fs::read(&path).with_context(|| format!("Unable to read {}", path.display()))
+    } else {
+        let n: u64 = value
+            .parse()
+            .with_context(|| format!("Invalid local value {}", value))?;
+        Ok(n.to_be_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_assignment() {
+        let mut key = [0; 32];
+        key[0] = 1;
+        key[31] = 0x10;
+        assert_eq!(
+            parse_assignment("0x10=0xabcd").unwrap(),
+            (key, vec![0xab, 0xcd])
+        );
+        assert_eq!(
+            parse_assignment("16=258").unwrap(),
+            (key, vec![0, 0, 0, 0, 0, 0, 1, 2])
+        );
+        assert!(parse_assignment("1").is_err());
+        assert!(parse_assignment("x=1").is_err());
+        assert!(parse_assignment("1=abc").is_err());
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("input.bin"), b"file data").unwrap();
+        let config = dir.path().join("inputs.toml");
+        std::fs::write(
+            &config,
+            "[local]\n1 = \"0xff\"\n2 = 7\n3 = \"@input.bin\"\n",
+        )
+        .unwrap();
+
+        let inputs = from_toml(&config).unwrap();
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs[&local_key(1)], [0xff]);
+        assert_eq!(inputs[&local_key(2)], 7_u64.to_be_bytes());
+        assert_eq!(inputs[&local_key(3)], b"file data");
+    }
+}
@@ -1,14 +1,33 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use cannon_io::oracle::KeyType;
 use clap::Parser;
-use log::debug;
-use preimage_provider::PreimageProvider;
+use log::{debug, error, info};
+use preimage_server::chaos::{ChaosProvider, Fragmented};
+use preimage_server::error::{key_type_name, MissingPreimageError};
+use preimage_server::hint_handler::{HintHandler, LogHintHandler};
+use preimage_server::listen::{serve_connections, Connection, ListenAddr, Listener};
+use preimage_server::preimage_provider::{
+    mismatched_keys, ArchiveProvider, AsyncPreimageProvider, BeaconApiProvider, DirectoryProvider,
+    DiskKvProvider, FallbackProvider, KeccakRpcProvider, RoutingProvider, VerifyingProvider,
+};
+use preimage_server::recording::Recording;
+use preimage_server::stats::Stats;
+use preimage_server::store::{self, Format};
+use preimage_server::{local_keys, raw};
+use preimage_server::{serve, serve_hints};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::os::fd::FromRawFd;
+use std::time::Duration;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::signal::unix::{signal, SignalKind};
 
 mod cli;
-mod preimage_provider;
+mod commands;
+
+// hint file descriptors
+const HCLIENT_RFD: i32 = 3;
+const HCLIENT_WFD: i32 = 4;
 
 // preimage file descriptors
 const PCLIENT_RFD: i32 = 5;
@@ -19,89 +38,310 @@ async fn main() -> Result<()> {
     env_logger::init();
     let args = cli::Cli::parse();
 
-    // check if args.path is a directory
-    let preimages = if args.path.is_dir() {
-        preimage_from_dir(args.path)
+    match &args.command {
+        Some(cli::Command::Keys { paths }) => return print_keys(paths),
+        Some(cli::Command::Add {
+            store,
+            files,
+            key_type,
+            local,
+            output_args,
+        }) => return commands::add(store, files, *key_type, local, output_args),
+        Some(cli::Command::Get { store, key, hex }) => {
+            return commands::get(store, key, *hex).await
+        }
+        Some(cli::Command::List { store }) => return commands::list(store),
+        Some(cli::Command::Verify { stores }) => return commands::verify(stores),
+        Some(cli::Command::Merge {
+            output,
+            inputs,
+            output_args,
+        }) => return commands::merge(output, inputs, output_args),
+        Some(cli::Command::Convert {
+            input,
+            output,
+            output_args,
+        }) => return commands::convert(input, output, output_args),
+        None => {}
+    }
+
+    // archives and directories are served straight from disk rather than loaded
+    let mut preimages = HashMap::new();
+    let mut on_disk: Option<Box<dyn AsyncPreimageProvider>> = None;
+    let format = match (&args.path, args.format) {
+        (Some(_), Some(format)) => Some(format),
+        (Some(path), None) => Some(Format::detect_strict(path)?),
+        (None, _) => None,
+    };
+    if let (Some(path), Some(format)) = (&args.path, format) {
+        match format {
+            Format::Json => preimages = store::load_as(path, format)?,
+            Format::Directory => on_disk = Some(Box::new(DirectoryProvider::new(path))),
+            Format::DiskKv => on_disk = Some(Box::new(DiskKvProvider::new(path))),
+            Format::Archive => on_disk = Some(Box::new(ArchiveProvider::open(path)?)),
+        }
+    }
+    for path in &args.raw {
+        for preimage in raw::load(path)? {
+            preimages.insert(preimage.keccak256_key, preimage.data.clone());
+            preimages.insert(preimage.sha256_key, preimage.data);
+        }
+    }
+    // local inputs take precedence over any in the preimage file
+    if let Some(config) = &args.local_config {
+        preimages.extend(local_keys::from_toml(config)?);
+    }
+    preimages.extend(args.local);
+    let fallback = args
+        .fallback
+        .as_deref()
+        .map(store::load)
+        .transpose()?
+        .unwrap_or_default();
+    if args.verify {
+        // stores served from disk are read in full once here so every mismatch is reported before serving
+        let on_disk_preimages = match (&args.path, format) {
+            (Some(path), Some(format)) if on_disk.is_some() => store::load_as(path, format)?,
+            _ => HashMap::new(),
+        };
+        verify_at_load(&[&on_disk_preimages, &preimages, &fallback])?;
+    }
+    let rpc = args.rpc_url.map(|url| {
+        let provider = KeccakRpcProvider::new(url);
+        match args.rpc_cache_dir {
+            Some(dir) => provider.with_cache_dir(dir),
+            None => provider,
+        }
+    });
+    let beacon = args.beacon_url.map(BeaconApiProvider::new);
+
+    let mut hint_handlers: Vec<Box<dyn HintHandler>> = vec![Box::new(LogHintHandler)];
+    if let Some(rpc) = &rpc {
+        hint_handlers.push(Box::new(rpc.hint_handler()));
+    }
+    if let Some(beacon) = &beacon {
+        hint_handlers.push(Box::new(beacon.hint_handler()));
+    }
+
+    // The hint handler may add preimages while requests are being served. Both run on the same task so
+    // a RefCell is sufficient to share the store
+    let preimages = RefCell::new(preimages);
+    // Each remote provider only serves keys of its own type
+    let mut remote = RoutingProvider::new();
+    if let Some(rpc) = rpc {
+        remote = remote.with_route(KeyType::Keccak256, rpc);
+    }
+    if let Some(beacon) = beacon {
+        remote = remote.with_route(KeyType::Sha256, beacon);
+    }
+    let disk_kv = args.disk_kv.map(DiskKvProvider::new);
+    // a store served from disk is the main store as much as one loaded into memory, so only what falls through
+    // both is a miss
+    let main = FallbackProvider::new(&preimages, on_disk);
+    let local = FallbackProvider::new(fallback, disk_kv);
+    let stats = Stats::new();
+    let provider = FallbackProvider::new(
+        main,
+        stats.miss_provider(FallbackProvider::new(local, remote)),
+    );
+    // Preimages that are loaded lazily, from disk, hints or remote providers, are verified as they are served
+    let provider: Box<dyn AsyncPreimageProvider + '_> = if args.verify {
+        Box::new(VerifyingProvider::new(provider))
     } else {
-        // else assume it is a json file
-        let json_str = std::fs::read_to_string(args.path).expect("Unable to read preimage file");
-        preimage_from_json_str(&json_str)
+        Box::new(provider)
     };
+    let recording = (args.record_log.is_some() || args.export.is_some()).then(Recording::new);
+    let (provider, hint_handler): (Box<dyn AsyncPreimageProvider + '_>, Box<dyn HintHandler>) =
+        match &recording {
+            Some(recording) => (
+                Box::new(recording.provider(provider)),
+                Box::new(recording.hint_handler(hint_handlers)),
+            ),
+            None => (provider, Box::new(hint_handlers)),
+        };
+    // faults are injected last so the recording holds the preimages as they should have been served
+    let provider: Box<dyn AsyncPreimageProvider + '_> = if args.chaos {
+        Box::new(
+            ChaosProvider::new(provider, args.chaos_seed)
+                .with_max_delay(Duration::from_millis(args.chaos_max_delay_ms))
+                .with_truncated(args.chaos_truncate)
+                .with_corrupted(args.chaos_corrupt),
+        )
+    } else {
+        provider
+    };
+    let fragment = args
+        .chaos
+        .then_some((args.chaos_seed, args.chaos_max_chunk));
+    let provider = stats.provider(provider);
+    let hint_handler = stats.hint_handler(hint_handler);
 
-    let reader = unsafe { File::from_raw_fd(PCLIENT_RFD) };
-    let writer = unsafe { File::from_raw_fd(PCLIENT_WFD) };
+    let serving = async {
+        match &args.listen {
+            Some(addr) => {
+                let hint_addr = args.hint_listen.as_ref();
+                serve_listening(
+                    addr,
+                    hint_addr,
+                    &provider,
+                    hint_handler,
+                    &preimages,
+                    fragment,
+                )
+                .await
+            }
+            None => {
+                let hint_reader =
+                    fragment_stream(fragment, 0, unsafe { File::from_raw_fd(HCLIENT_RFD) });
+                let hint_writer =
+                    fragment_stream(fragment, 1, unsafe { File::from_raw_fd(HCLIENT_WFD) });
+                let reader =
+                    fragment_stream(fragment, 2, unsafe { File::from_raw_fd(PCLIENT_RFD) });
+                let writer =
+                    fragment_stream(fragment, 3, unsafe { File::from_raw_fd(PCLIENT_WFD) });
+                tokio::try_join!(
+                    serve_hints(hint_reader, hint_writer, hint_handler, &preimages),
+                    serve(reader, writer, &provider),
+                )
+                .map(|_| ())
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = serving => result,
+        result = shutdown_signal() => result,
+        _ = stats.log_every(Duration::from_secs(args.stats_interval)) => unreachable!("stats are logged forever"),
+    };
 
-    wait_for_requests(reader, writer, preimages).await?;
+    let report = stats.report();
+    info!("Run report: {}", report);
+    if let Some(path) = &args.report {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
 
+    // write out the recording however the run ended
+    if let Some(recording) = &recording {
+        if let Some(path) = &args.record_log {
+            recording.write_log(path)?;
+        }
+        if let Some(path) = &args.export {
+            recording.write_preimages(path, args.export_format)?;
+        }
+    }
+
+    match result {
+        Err(e) if args.on_missing == cli::OnMissing::Exit && e.is::<MissingPreimageError>() => {
+            std::process::exit(args.missing_exit_code)
+        }
+        result => result,
+    }
+}
+
+/// Serve preimage requests from clients connecting to `addr`, and hints from clients connecting to `hint_addr`,
+/// until a listener can no longer accept connections
+async fn serve_listening(
+    addr: &ListenAddr,
+    hint_addr: Option<&ListenAddr>,
+    provider: &dyn AsyncPreimageProvider,
+    hint_handler: impl HintHandler,
+    preimages: &RefCell<HashMap<[u8; 32], Vec<u8>>>,
+    fragment: Option<(u64, usize)>,
+) -> Result<()> {
+    let listener = Listener::bind(addr).await?;
+    let hint_listener = match hint_addr {
+        Some(addr) => Some(Listener::bind(addr).await?),
+        None => None,
+    };
+
+    // every hint connection shares the one handler
+    let hint_handler = RefCell::new(hint_handler);
+    // every connection is fragmented differently, preimage connections on even channels and hint connections on odd
+    let mut hint_connections = 0;
+    let serve_hint_connections = async {
+        match hint_listener {
+            Some(listener) => {
+                serve_connections(listener, |connection| {
+                    let channel = 2 * hint_connections + 1;
+                    hint_connections += 1;
+                    let (reader, writer) =
+                        tokio::io::split(fragment_stream(fragment, channel, connection));
+                    serve_hints(reader, writer, &hint_handler, preimages)
+                })
+                .await
+            }
+            None => Ok(()),
+        }
+    };
+    let mut connections = 0;
+    tokio::try_join!(
+        serve_connections(listener, |connection| {
+            let channel = 2 * connections;
+            connections += 1;
+            let (reader, writer) = tokio::io::split(fragment_stream(fragment, channel, connection));
+            serve(reader, writer, provider)
+        }),
+        serve_hint_connections,
+    )?;
     Ok(())
 }
 
-/// Load json string into a preimage HashMap.
-///
-/// # Panics
-/// This will panic if:
-///     - the json string is not valid json
-///     - if they key is not valid hex or not 32 bytes
-///     - if the value is not valid hex
-fn preimage_from_json_str(json: &str) -> HashMap<[u8; 32], Vec<u8>> {
-    let json: HashMap<String, String> = serde_json::from_str(&json).expect("Unable to parse");
-
-    let mut preimages = HashMap::<[u8; 32], Vec<u8>>::new();
-    for (k, v) in json.iter() {
-        let k = hex::decode(k).expect("Unable to decode key");
-        assert!(k.len() == 32, "Key must be 32 bytes");
-        let mut key = [0; 32];
-        key.copy_from_slice(&k);
-        let v = hex::decode(v).expect("Unable to decode value");
-        preimages.insert(key, v);
-    }
-    debug!("Loaded {} preimages from file", preimages.len());
-
-    preimages
+/// Wait for SIGINT or SIGTERM so the run can be reported and recorded before exiting
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    info!("Shutting down");
+    Ok(())
 }
 
-/// Load a directory full of files named with their preimage key
-///
-/// # Panics
-/// This will panic if:
-///     - any file name is not valid hex or not 32 bytes
-fn preimage_from_dir(dir: std::path::PathBuf) -> HashMap<[u8; 32], Vec<u8>> {
-    let mut preimages = HashMap::<[u8; 32], Vec<u8>>::new();
-    for file in std::fs::read_dir(dir).expect("Unable to read directory") {
-        let file = file.expect("Unable to read file");
-        let fname = file.file_name();
-        let k = hex::decode(fname.to_str().unwrap()).expect("invalid hex in filename");
-        let mut key = [0; 32];
-        key.copy_from_slice(&k);
-
-        let value = std::fs::read(file.path()).expect("Unable to read file");
-        preimages.insert(key, value);
-    }
-    debug!("Loaded {} preimages from directory", preimages.len());
-    preimages
+/// With `--chaos`, split every read and write on `stream` into small chunks. `channel` varies the seed per stream
+fn fragment_stream(
+    fragment: Option<(u64, usize)>,
+    channel: u64,
+    stream: impl Connection + 'static,
+) -> Box<dyn Connection> {
+    match fragment {
+        Some((seed, max_chunk)) => Box::new(Fragmented::new(
+            stream,
+            seed.wrapping_add(channel),
+            max_chunk,
+        )),
+        None => Box::new(stream),
+    }
 }
 
-/// Infinitely wait for new requests to be forwarded from the emulator on the reader channel
-/// On each received request try and retrieve a pre-image and send it to the guest on the writer channel
-async fn wait_for_requests(
-    mut reader: File,
-    mut writer: File,
-    preimages: impl PreimageProvider,
-) -> Result<()> {
-    loop {
-        let mut key_buffer = [0; 32];
-        reader.read(&mut key_buffer).await?;
-        debug!("Received key bytes: {:?}", &key_buffer);
-
-        if let Some(data) = preimages.get(&key_buffer) {
-            // first it needs to write the length as a u64 big-endian
-            let length: u64 = data.len() as u64;
-            writer.write(&length.to_be_bytes()).await?;
-
-            // then write the actual data
-            writer.write(&data).await?;
-        } else {
-            panic!("Guest requested preimage that does not exist")
+/// Check every keccak256 and sha256 preimage matches its key, reporting all that do not
+fn verify_at_load(stores: &[&HashMap<[u8; 32], Vec<u8>>]) -> Result<()> {
+    let mismatched: Vec<[u8; 32]> = stores
+        .iter()
+        .flat_map(|store| mismatched_keys(store.iter()))
+        .collect();
+    for key in &mismatched {
+        error!(
+            "Preimage for key 0x{} ({}) does not match its hash",
+            hex::encode(key),
+            key_type_name(key[0])
+        );
+    }
+    if !mismatched.is_empty() {
+        bail!("{} preimages do not match their keys", mismatched.len());
+    }
+    debug!("Verified all preimages");
+    Ok(())
+}
+
+fn print_keys(paths: &[std::path::PathBuf]) -> Result<()> {
+    for path in paths {
+        for preimage in raw::load(path)? {
+            println!(
+                "{}: keccak256=0x{} sha256=0x{}",
+                preimage.path.display(),
+                hex::encode(preimage.keccak256_key),
+                hex::encode(preimage.sha256_key)
+            );
         }
     }
+    Ok(())
 }
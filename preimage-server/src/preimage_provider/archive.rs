@@ -0,0 +1,344 @@
+//! A single file archive of preimages that is read through a memory map
+//!
+//! The layout, with all integers little-endian, is
+//! - a 24 byte header: the magic bytes `CNNPRIMG`, a u32 version, u32 flags and the u64 number of entries
+//! - the index: one 48 byte entry per preimage of its key, u64 offset into the data section and u64 stored length,
+//!   sorted by key
+//! - the data section: every stored value, one after the other
+//!
+//! When the compressed flag is set every value is stored LZ4 compressed with its uncompressed size prepended.
+//! Lookups binary search the index so opening an archive does not read it and only the pages that are used
+//! are loaded into memory. Uncompressed values are streamed to the guest straight from the memory map.
+
+use super::{PreimageProvider, PreimageStream};
+use anyhow::{anyhow, bail, Context, Result};
+use log::error;
+use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"CNNPRIMG";
+const VERSION: u32 = 1;
+const FLAG_COMPRESSED: u32 = 1;
+const HEADER_LEN: usize = 24;
+const ENTRY_LEN: usize = 48;
+/// LZ4 cannot compress by more than this, so a larger prepended size can only come from a corrupt archive
+const MAX_COMPRESSION_RATIO: usize = 256;
+
+/// Check if the file at `path` is a preimage archive by its magic bytes
+pub fn is_archive(path: &Path) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == MAGIC
+}
+
+/// Write `preimages` to a new archive at `path`, optionally compressing every value
+///
+/// Values are streamed to the file so only the keys and their offsets are held in memory.
+pub fn write_archive<'a>(
+    path: &Path,
+    preimages: impl IntoIterator<Item = (&'a [u8; 32], &'a Vec<u8>)>,
+    compress: bool,
+) -> Result<()> {
+    let preimages: BTreeMap<&[u8; 32], &Vec<u8>> = preimages.into_iter().collect();
+    let file =
+        File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    let flags = if compress { FLAG_COMPRESSED } else { 0 };
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&flags.to_le_bytes())?;
+    writer.write_all(&(preimages.len() as u64).to_le_bytes())?;
+
+    // the index is written once the offsets of the values are known
+    let data_start = (HEADER_LEN + preimages.len() * ENTRY_LEN) as u64;
+    writer.seek(SeekFrom::Start(data_start))?;
+    let mut index = Vec::with_capacity(preimages.len());
+    let mut offset = 0;
+    for (key, value) in &preimages {
+        let len = if compress {
+            let compressed = lz4_flex::compress_prepend_size(value);
+            writer.write_all(&compressed)?;
+            compressed.len()
+        } else {
+            writer.write_all(value)?;
+            value.len()
+        } as u64;
+        index.push((*key, offset, len));
+        offset += len;
+    }
+
+    writer.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+    for (key, offset, len) in index {
+        writer.write_all(key)?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serves preimages from an archive written by [`write_archive`]
+///
+/// # Examples
+/// ```no_run
+/// use preimage_server::preimage_provider::{ArchiveProvider, PreimageProvider};
+///
+/// let provider = ArchiveProvider::open("preimages.bin".as_ref()).unwrap();
+/// let data = provider.get(&[2; 32]);
+/// ```
+pub struct ArchiveProvider {
+    mmap: Mmap,
+    compressed: bool,
+    len: usize,
+}
+
+impl ArchiveProvider {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        // Safety: the archive must not be modified while it is being served. Archives are written once and
+        // only read afterwards
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_LEN || &mmap[..MAGIC.len()] != MAGIC {
+            bail!("{} is not a preimage archive", path.display());
+        }
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        if version != VERSION {
+            bail!("Unsupported preimage archive version {}", version);
+        }
+        let flags = u32::from_le_bytes(mmap[12..16].try_into().unwrap());
+        let len = u64::from_le_bytes(mmap[16..24].try_into().unwrap());
+        // the count is untrusted so the size of the index it implies may not even fit in memory
+        let Some((len, index_end)) = usize::try_from(len).ok().and_then(|len| {
+            let index_end = len.checked_mul(ENTRY_LEN)?.checked_add(HEADER_LEN)?;
+            Some((len, index_end))
+        }) else {
+            bail!(
+                "Preimage archive {} has an invalid entry count {}",
+                path.display(),
+                len
+            );
+        };
+        if mmap.len() < index_end {
+            bail!("Preimage archive {} is truncated", path.display());
+        }
+
+        Ok(Self {
+            mmap,
+            compressed: flags & FLAG_COMPRESSED != 0,
+            len,
+        })
+    }
+
+    /// The number of preimages in the archive
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Every key in the archive, in order
+    pub fn keys(&self) -> impl Iterator<Item = [u8; 32]> + '_ {
+        (0..self.len).map(|i| self.entry(i).0)
+    }
+
+    /// Every key in the archive with the length of its preimage, in order. The length of a compressed preimage is
+    /// read from the size prepended to its value, so only the first bytes of each value are read
+    pub fn entries(&self) -> impl Iterator<Item = ([u8; 32], u64)> + '_ {
+        (0..self.len).map(|i| {
+            let (key, offset, len) = self.entry(i);
+            if !self.compressed {
+                return (key, len);
+            }
+            let size = self
+                .stored(offset, len.min(4))
+                .ok()
+                .and_then(|size| size.try_into().ok())
+                .map_or(0, u32::from_le_bytes);
+            (key, size as u64)
+        })
+    }
+
+    fn entry(&self, i: usize) -> ([u8; 32], u64, u64) {
+        let entry = &self.mmap[HEADER_LEN + i * ENTRY_LEN..][..ENTRY_LEN];
+        (
+            entry[..32].try_into().unwrap(),
+            u64::from_le_bytes(entry[32..40].try_into().unwrap()),
+            u64::from_le_bytes(entry[40..48].try_into().unwrap()),
+        )
+    }
+
+    fn find(&self, key: &[u8; 32]) -> Option<(u64, u64)> {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (mid_key, offset, len) = self.entry(mid);
+            match mid_key.cmp(key) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some((offset, len)),
+            }
+        }
+        None
+    }
+
+    /// The stored bytes of the value at `offset`
+    fn stored(&self, offset: u64, len: u64) -> Result<&[u8]> {
+        let start = HEADER_LEN as u64 + self.len as u64 * ENTRY_LEN as u64 + offset;
+        usize::try_from(start)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(start, len)| self.mmap.get(start..start.checked_add(len)?))
+            .ok_or_else(|| anyhow!("entry is outside of the archive"))
+    }
+
+    fn try_open(&self, key: &[u8; 32]) -> Result<Option<PreimageStream>> {
+        let Some((offset, len)) = self.find(key) else {
+            return Ok(None);
+        };
+        let stored = self.stored(offset, len)?;
+        if self.compressed {
+            Ok(Some(decompress(stored)?.into()))
+        } else {
+            // served straight from the memory map
+            Ok(Some(PreimageStream {
+                len,
+                reader: Box::new(stored),
+            }))
+        }
+    }
+}
+
+impl PreimageProvider for ArchiveProvider {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let (offset, len) = self.find(key)?;
+        let stored = self.stored(offset, len);
+        let data = if self.compressed {
+            stored.and_then(decompress)
+        } else {
+            stored.map(<[u8]>::to_vec)
+        };
+        data.map_err(|e| log_read_error(key, e)).ok()
+    }
+
+    fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        self.try_open(key)
+            .map_err(|e| log_read_error(key, e))
+            .ok()?
+    }
+}
+
+/// Decompress a value, checking the prepended size is possible before allocating for it
+fn decompress(stored: &[u8]) -> Result<Vec<u8>> {
+    let size = stored
+        .get(..4)
+        .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+        .ok_or_else(|| anyhow!("compressed entry is missing its size"))?;
+    if size > stored.len().saturating_mul(MAX_COMPRESSION_RATIO) {
+        bail!(
+            "compressed entry of {} bytes claims an impossible size of {} bytes",
+            stored.len(),
+            size
+        );
+    }
+    Ok(lz4_flex::decompress_size_prepended(stored)?)
+}
+
+fn log_read_error(key: &[u8; 32], e: anyhow::Error) {
+    error!(
+        "Unable to read preimage 0x{} from archive: {}",
+        hex::encode(key),
+        e
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let preimages = HashMap::from([
+            ([3; 32], vec![0xab; 1000]),
+            ([1; 32], b"first".to_vec()),
+            ([2; 32], Vec::new()),
+        ]);
+
+        for compress in [false, true] {
+            let path = dir.path().join(format!("preimages-{}.bin", compress));
+            write_archive(&path, &preimages, compress).unwrap();
+            assert!(is_archive(&path));
+
+            let archive = ArchiveProvider::open(&path).unwrap();
+            assert_eq!(archive.len(), 3);
+            assert_eq!(
+                archive.keys().collect::<Vec<_>>(),
+                [[1; 32], [2; 32], [3; 32]]
+            );
+            assert_eq!(
+                archive.entries().collect::<Vec<_>>(),
+                [([1; 32], 5), ([2; 32], 0), ([3; 32], 1000)]
+            );
+            for (key, value) in &preimages {
+                assert_eq!(archive.get(key).as_ref(), Some(value));
+            }
+            assert_eq!(archive.get(&[4; 32]), None);
+            assert!(archive.open(&[4; 32]).is_none());
+
+            let mut streamed = Vec::new();
+            let mut preimage = archive.open(&[3; 32]).unwrap();
+            tokio::io::copy(&mut preimage.reader, &mut streamed)
+                .await
+                .unwrap();
+            assert_eq!(preimage.len, 1000);
+            assert_eq!(streamed, preimages[&[3; 32]]);
+            assert_eq!(archive.get(&[0; 32]), None);
+        }
+
+        let compressed = std::fs::metadata(dir.path().join("preimages-true.bin")).unwrap();
+        let uncompressed = std::fs::metadata(dir.path().join("preimages-false.bin")).unwrap();
+        assert!(compressed.len() < uncompressed.len());
+    }
+
+    #[test]
+    fn test_rejects_corrupt_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preimages.bin");
+        write_archive(&path, &HashMap::from([([1; 32], vec![0; 10])]), true).unwrap();
+        let archive = std::fs::read(&path).unwrap();
+
+        // an entry count whose index would overflow
+        let mut corrupt = archive.clone();
+        corrupt[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &corrupt).unwrap();
+        assert!(ArchiveProvider::open(&path).is_err());
+
+        // a compressed value claiming to be far larger than it could be
+        let mut corrupt = archive;
+        corrupt[HEADER_LEN + ENTRY_LEN..][..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &corrupt).unwrap();
+        let provider = ArchiveProvider::open(&path).unwrap();
+        assert_eq!(provider.get(&[1; 32]), None);
+        assert!(provider.open(&[1; 32]).is_none());
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preimages.json");
+        std::fs::write(&path, "{}").unwrap();
+        assert!(!is_archive(&path));
+        assert!(ArchiveProvider::open(&path).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+use super::ssz::{Nodes, Ssz};
+use super::AsyncPreimageProvider;
+use crate::hint_handler::{Hint, HintHandler};
+use crate::preimage_provider::PreimageStore;
+use anyhow::{bail, ensure, Result};
+use async_trait::async_trait;
+use cannon_io::oracle::KeyType;
+use log::{debug, warn};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The only fork whose types are currently supported
+const SUPPORTED_FORK: &str = "deneb";
+
+/// A beacon chain object that can be requested by its root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BeaconObject {
+    Block([u8; 32]),
+    State([u8; 32]),
+}
+
+impl BeaconObject {
+    /// The object for a hint of the form `beacon-block 0x<block root>` or `beacon-state 0x<state root>`
+    fn from_hint(hint: &Hint) -> Option<Self> {
+        let root: [u8; 32] = hint.data.as_slice().try_into().ok()?;
+        match hint.hint_type.as_str() {
+            "beacon-block" => Some(BeaconObject::Block(root)),
+            "beacon-state" => Some(BeaconObject::State(root)),
+            _ => None,
+        }
+    }
+
+    fn root(&self) -> [u8; 32] {
+        match self {
+            BeaconObject::Block(root) | BeaconObject::State(root) => *root,
+        }
+    }
+}
+
+#[derive(Default)]
+struct BeaconState {
+    hinted: HashMap<[u8; 32], BeaconObject>,
+    nodes: HashMap<[u8; 32], Vec<u8>>,
+}
+
+/// Serves sha256 preimages of beacon chain data retrieved from a Beacon API endpoint
+///
+/// When the guest hints `beacon-block 0x<root>` or `beacon-state 0x<root>` the SSZ encoded object is fetched
+/// once its root is requested. It is then merkleized and every node of the tree is made available under its
+/// sha256 key, so the guest can walk from the root down to any field it needs.
+///
+/// Only objects from the Deneb fork with mainnet preset sizes are currently supported.
+pub struct BeaconApiProvider {
+    client: reqwest::Client,
+    url: String,
+    state: Rc<RefCell<BeaconState>>,
+}
+
+impl BeaconApiProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            state: Rc::default(),
+        }
+    }
+
+    /// Handler that must receive the guest hints so the provider knows what to fetch
+    pub fn hint_handler(&self) -> BeaconHintHandler {
+        BeaconHintHandler {
+            state: self.state.clone(),
+        }
+    }
+
+    async fn fetch(&self, object: BeaconObject) -> Result<Nodes> {
+        let path = match object {
+            BeaconObject::Block(_) => "eth/v2/beacon/blocks",
+            BeaconObject::State(_) => "eth/v2/debug/beacon/states",
+        };
+        let root = format!("0x{}", hex::encode(object.root()));
+        let response = self
+            .client
+            .get(format!(
+                "{}/{}/{}",
+                self.url.trim_end_matches('/'),
+                path,
+                root
+            ))
+            .header("Accept", "application/octet-stream")
+            .send()
+            .await?
+            .error_for_status()?;
+        if let Some(fork) = response.headers().get("Eth-Consensus-Version") {
+            if fork != SUPPORTED_FORK {
+                bail!("Unsupported fork {:?} for {}", fork, root);
+            }
+        }
+        let bytes = response.bytes().await?;
+
+        let mut nodes = Nodes::new();
+        let computed = match object {
+            // The block root is the root of the message, not of the signed block. The message is variable size
+            // so the signed block starts with its offset
+            BeaconObject::Block(_) => {
+                ensure!(bytes.len() >= 4, "Signed block for {} is too short", root);
+                let offset = u32::from_le_bytes(bytes[..4].try_into()?) as usize;
+                let message = bytes.get(offset..).unwrap_or_default();
+                deneb::beacon_block().hash_tree_root(message, &mut nodes)?
+            }
+            BeaconObject::State(_) => deneb::beacon_state().hash_tree_root(&bytes, &mut nodes)?,
+        };
+        if computed != object.root() {
+            bail!("Data returned for {} does not match its root", root);
+        }
+        Ok(nodes)
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncPreimageProvider for BeaconApiProvider {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        if key[0] != KeyType::Sha256 as u8 {
+            return None;
+        }
+        if let Some(data) = self.state.borrow().nodes.get(key) {
+            return Some(data.clone());
+        }
+        let object = match self.state.borrow().hinted.get(key) {
+            Some(object) => *object,
+            None => {
+                debug!("No hint received for key 0x{}", hex::encode(key));
+                return None;
+            }
+        };
+
+        let nodes = match self.fetch(object).await {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                warn!("Unable to retrieve beacon data: {}", e);
+                return None;
+            }
+        };
+        debug!("Registered {} nodes for {:?}", nodes.len(), object);
+        let mut state = self.state.borrow_mut();
+        for (hash, preimage) in nodes {
+            state.nodes.insert(sha256_key(hash), preimage.to_vec());
+        }
+        state.nodes.get(key).cloned()
+    }
+}
+
+/// Records the roots hinted by the guest for a [`BeaconApiProvider`]
+pub struct BeaconHintHandler {
+    state: Rc<RefCell<BeaconState>>,
+}
+
+impl HintHandler for BeaconHintHandler {
+    fn handle_hint(&mut self, hint: &Hint, _preimages: &mut dyn PreimageStore) -> Result<()> {
+        // other hints are for other handlers
+        if let Some(object) = BeaconObject::from_hint(hint) {
+            let key = sha256_key(object.root());
+            self.state.borrow_mut().hinted.insert(key, object);
+        }
+        Ok(())
+    }
+}
+
+fn sha256_key(hash: [u8; 32]) -> [u8; 32] {
+    let mut key = hash;
+    key[0] = KeyType::Sha256 as u8;
+    key
+}
+
+/// SSZ schemas of the Deneb fork using mainnet preset sizes
+mod deneb {
+    use super::Ssz;
+
+    const SLOTS_PER_HISTORICAL_ROOT: usize = 8192;
+    const EPOCHS_PER_HISTORICAL_VECTOR: usize = 65536;
+    const EPOCHS_PER_SLASHINGS_VECTOR: usize = 8192;
+    const HISTORICAL_ROOTS_LIMIT: usize = 1 << 24;
+    const VALIDATOR_REGISTRY_LIMIT: usize = 1 << 40;
+    const ETH1_DATA_VOTES_LIMIT: usize = 2048;
+    const JUSTIFICATION_BITS_LENGTH: usize = 4;
+    const SYNC_COMMITTEE_SIZE: usize = 512;
+    const MAX_VALIDATORS_PER_COMMITTEE: usize = 2048;
+    const MAX_PROPOSER_SLASHINGS: usize = 16;
+    const MAX_ATTESTER_SLASHINGS: usize = 2;
+    const MAX_ATTESTATIONS: usize = 128;
+    const MAX_DEPOSITS: usize = 16;
+    const MAX_VOLUNTARY_EXITS: usize = 16;
+    const MAX_BLS_TO_EXECUTION_CHANGES: usize = 16;
+    const MAX_BLOB_COMMITMENTS_PER_BLOCK: usize = 4096;
+    const DEPOSIT_PROOF_LENGTH: usize = 33;
+    const MAX_BYTES_PER_TRANSACTION: usize = 1 << 30;
+    const MAX_TRANSACTIONS_PER_PAYLOAD: usize = 1 << 20;
+    const BYTES_PER_LOGS_BLOOM: usize = 256;
+    const MAX_EXTRA_DATA_BYTES: usize = 32;
+    const MAX_WITHDRAWALS_PER_PAYLOAD: usize = 16;
+
+    fn uint64() -> Ssz {
+        Ssz::Uint(8)
+    }
+
+    fn root() -> Ssz {
+        Ssz::byte_vector(32)
+    }
+
+    fn signature() -> Ssz {
+        Ssz::byte_vector(96)
+    }
+
+    fn pubkey() -> Ssz {
+        Ssz::byte_vector(48)
+    }
+
+    fn address() -> Ssz {
+        Ssz::byte_vector(20)
+    }
+
+    fn signed(message: Ssz) -> Ssz {
+        Ssz::Container(vec![message, signature()])
+    }
+
+    fn checkpoint() -> Ssz {
+        Ssz::Container(vec![uint64(), root()])
+    }
+
+    fn fork() -> Ssz {
+        Ssz::Container(vec![Ssz::byte_vector(4), Ssz::byte_vector(4), uint64()])
+    }
+
+    fn eth1_data() -> Ssz {
+        Ssz::Container(vec![root(), uint64(), root()])
+    }
+
+    fn beacon_block_header() -> Ssz {
+        Ssz::Container(vec![uint64(), uint64(), root(), root(), root()])
+    }
+
+    fn attestation_data() -> Ssz {
+        Ssz::Container(vec![uint64(), uint64(), root(), checkpoint(), checkpoint()])
+    }
+
+    fn indexed_attestation() -> Ssz {
+        Ssz::Container(vec![
+            Ssz::list(uint64(), MAX_VALIDATORS_PER_COMMITTEE),
+            attestation_data(),
+            signature(),
+        ])
+    }
+
+    fn attestation() -> Ssz {
+        Ssz::Container(vec![
+            Ssz::Bitlist(MAX_VALIDATORS_PER_COMMITTEE),
+            attestation_data(),
+            signature(),
+        ])
+    }
+
+    fn deposit() -> Ssz {
+        let deposit_data = Ssz::Container(vec![pubkey(), root(), uint64(), signature()]);
+        Ssz::Container(vec![
+            Ssz::vector(root(), DEPOSIT_PROOF_LENGTH),
+            deposit_data,
+        ])
+    }
+
+    fn sync_aggregate() -> Ssz {
+        Ssz::Container(vec![Ssz::Bitvector(SYNC_COMMITTEE_SIZE), signature()])
+    }
+
+    fn sync_committee() -> Ssz {
+        Ssz::Container(vec![Ssz::vector(pubkey(), SYNC_COMMITTEE_SIZE), pubkey()])
+    }
+
+    fn withdrawal() -> Ssz {
+        Ssz::Container(vec![uint64(), uint64(), address(), uint64()])
+    }
+
+    /// The fields shared by the execution payload and its header, with the transactions and withdrawals
+    /// fields given
+    fn execution_payload_with(transactions: Ssz, withdrawals: Ssz) -> Ssz {
+        Ssz::Container(vec![
+            root(),
+            address(),
+            root(),
+            root(),
+            Ssz::byte_vector(BYTES_PER_LOGS_BLOOM),
+            root(),
+            uint64(),
+            uint64(),
+            uint64(),
+            uint64(),
+            Ssz::byte_list(MAX_EXTRA_DATA_BYTES),
+            Ssz::Uint(32),
+            root(),
+            transactions,
+            withdrawals,
+            uint64(),
+            uint64(),
+        ])
+    }
+
+    fn beacon_block_body() -> Ssz {
+        let transactions = Ssz::list(
+            Ssz::byte_list(MAX_BYTES_PER_TRANSACTION),
+            MAX_TRANSACTIONS_PER_PAYLOAD,
+        );
+        let withdrawals = Ssz::list(withdrawal(), MAX_WITHDRAWALS_PER_PAYLOAD);
+        let bls_to_execution_change = Ssz::Container(vec![uint64(), pubkey(), address()]);
+        Ssz::Container(vec![
+            signature(),
+            eth1_data(),
+            root(),
+            Ssz::list(
+                Ssz::Container(vec![
+                    signed(beacon_block_header()),
+                    signed(beacon_block_header()),
+                ]),
+                MAX_PROPOSER_SLASHINGS,
+            ),
+            Ssz::list(
+                Ssz::Container(vec![indexed_attestation(), indexed_attestation()]),
+                MAX_ATTESTER_SLASHINGS,
+            ),
+            Ssz::list(attestation(), MAX_ATTESTATIONS),
+            Ssz::list(deposit(), MAX_DEPOSITS),
+            Ssz::list(
+                signed(Ssz::Container(vec![uint64(), uint64()])),
+                MAX_VOLUNTARY_EXITS,
+            ),
+            sync_aggregate(),
+            execution_payload_with(transactions, withdrawals),
+            Ssz::list(
+                signed(bls_to_execution_change),
+                MAX_BLS_TO_EXECUTION_CHANGES,
+            ),
+            Ssz::list(pubkey(), MAX_BLOB_COMMITMENTS_PER_BLOCK),
+        ])
+    }
+
+    pub fn beacon_block() -> Ssz {
+        Ssz::Container(vec![
+            uint64(),
+            uint64(),
+            root(),
+            root(),
+            beacon_block_body(),
+        ])
+    }
+
+    pub fn beacon_state() -> Ssz {
+        let validator = Ssz::Container(vec![
+            pubkey(),
+            root(),
+            uint64(),
+            Ssz::Bool,
+            uint64(),
+            uint64(),
+            uint64(),
+            uint64(),
+        ]);
+        Ssz::Container(vec![
+            uint64(),
+            root(),
+            uint64(),
+            fork(),
+            beacon_block_header(),
+            Ssz::vector(root(), SLOTS_PER_HISTORICAL_ROOT),
+            Ssz::vector(root(), SLOTS_PER_HISTORICAL_ROOT),
+            Ssz::list(root(), HISTORICAL_ROOTS_LIMIT),
+            eth1_data(),
+            Ssz::list(eth1_data(), ETH1_DATA_VOTES_LIMIT),
+            uint64(),
+            Ssz::list(validator, VALIDATOR_REGISTRY_LIMIT),
+            Ssz::list(uint64(), VALIDATOR_REGISTRY_LIMIT),
+            Ssz::vector(root(), EPOCHS_PER_HISTORICAL_VECTOR),
+            Ssz::vector(uint64(), EPOCHS_PER_SLASHINGS_VECTOR),
+            Ssz::list(Ssz::Uint(1), VALIDATOR_REGISTRY_LIMIT),
+            Ssz::list(Ssz::Uint(1), VALIDATOR_REGISTRY_LIMIT),
+            Ssz::Bitvector(JUSTIFICATION_BITS_LENGTH),
+            checkpoint(),
+            checkpoint(),
+            checkpoint(),
+            Ssz::list(uint64(), VALIDATOR_REGISTRY_LIMIT),
+            sync_committee(),
+            sync_committee(),
+            execution_payload_with(root(), root()),
+            uint64(),
+            uint64(),
+            Ssz::list(Ssz::Container(vec![root(), root()]), HISTORICAL_ROOTS_LIMIT),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preimage_provider::mock_http::{MockHttpServer, Request};
+    use sha2::{Digest, Sha256};
+
+    /// An empty Deneb block, SSZ encoded
+    fn empty_block() -> Vec<u8> {
+        // execution payload: fixed part with offsets for extra data, transactions and withdrawals
+        let payload_fixed = 32 + 20 + 32 + 32 + 256 + 32 + 8 * 4 + 4 + 32 + 32 + 4 + 4 + 8 + 8;
+        let mut payload = vec![0; payload_fixed];
+        for offset in [436, 504, 508] {
+            payload[offset..offset + 4].copy_from_slice(&(payload_fixed as u32).to_le_bytes());
+        }
+
+        // body: randao, eth1 data, graffiti, 5 list offsets, sync aggregate, payload and 2 list offsets
+        let body_fixed = 96 + 72 + 32 + 4 * 5 + 64 + 96 + 4 + 4 + 4;
+        let mut body = vec![0; body_fixed];
+        for field in 0..5 {
+            let at = 200 + field * 4;
+            body[at..at + 4].copy_from_slice(&(body_fixed as u32).to_le_bytes());
+        }
+        let payload_offset = 200 + 20 + 160;
+        body[payload_offset..payload_offset + 4]
+            .copy_from_slice(&(body_fixed as u32).to_le_bytes());
+        let after_payload = (body_fixed + payload.len()) as u32;
+        for at in [payload_offset + 4, payload_offset + 8] {
+            body[at..at + 4].copy_from_slice(&after_payload.to_le_bytes());
+        }
+        body.extend(payload);
+
+        let mut block = vec![0; 8 + 8 + 32 + 32];
+        block[..8].copy_from_slice(&7_u64.to_le_bytes());
+        block.extend_from_slice(&84_u32.to_le_bytes());
+        block.extend(body);
+
+        let mut signed = 100_u32.to_le_bytes().to_vec();
+        signed.extend_from_slice(&[0; 96]);
+        signed.extend(block);
+        signed
+    }
+
+    fn block_root(signed_block: &[u8]) -> [u8; 32] {
+        deneb::beacon_block()
+            .hash_tree_root(&signed_block[100..], &mut Nodes::new())
+            .unwrap()
+    }
+
+    async fn beacon_server(block: Vec<u8>) -> MockHttpServer {
+        MockHttpServer::start(move |request: &Request| {
+            if request.path.starts_with("/eth/v2/beacon/blocks/") {
+                (200, block.clone())
+            } else {
+                (404, Vec::new())
+            }
+        })
+        .await
+    }
+
+    fn hint(provider: &BeaconApiProvider, hint_type: &str, root: [u8; 32]) {
+        let hint = Hint {
+            hint_type: hint_type.to_string(),
+            data: root.to_vec(),
+        };
+        provider
+            .hint_handler()
+            .handle_hint(&hint, &mut HashMap::new())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hinted_block_tree_is_registered() {
+        let block = empty_block();
+        let root = block_root(&block);
+        let server = beacon_server(block).await;
+        let provider = BeaconApiProvider::new(&server.url);
+
+        hint(&provider, "beacon-block", root);
+        let root_node = provider.get(&sha256_key(root)).await.unwrap();
+        assert_eq!(root_node.len(), 64);
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(server.requests()[0].method, "GET");
+        assert_eq!(
+            server.requests()[0].path,
+            format!("/eth/v2/beacon/blocks/0x{}", hex::encode(root))
+        );
+
+        // walk down the left side of the tree to the slot without any further requests
+        let mut node = root_node;
+        let mut hash = root;
+        for _ in 0..3 {
+            assert_eq!(Sha256::digest(&node)[..], hash[..]);
+            hash = node[..32].try_into().unwrap();
+            if let Some(child) = provider.get(&sha256_key(hash)).await {
+                node = child;
+            }
+        }
+        assert_eq!(hash[..8], 7_u64.to_le_bytes());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_root_is_rejected() {
+        let server = beacon_server(empty_block()).await;
+        let provider = BeaconApiProvider::new(&server.url);
+
+        hint(&provider, "beacon-block", [0x11; 32]);
+        assert_eq!(provider.get(&sha256_key([0x11; 32])).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unhinted_and_non_sha256_keys_are_not_fetched() {
+        let server = beacon_server(empty_block()).await;
+        let provider = BeaconApiProvider::new(&server.url);
+
+        assert_eq!(provider.get(&sha256_key([0x22; 32])).await, None);
+        assert_eq!(provider.get(&[2; 32]).await, None);
+        assert!(server.requests().is_empty());
+    }
+}
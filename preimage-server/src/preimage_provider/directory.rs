@@ -0,0 +1,76 @@
+use super::{AsyncPreimageProvider, PreimageStream};
+use async_trait::async_trait;
+use log::warn;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Serves preimages from a directory of files named with their hex key, as written by `--rpc-cache-dir`
+///
+/// Files are opened as they are requested and streamed to the guest so the directory is never loaded into
+/// memory.
+pub struct DirectoryProvider {
+    dir: PathBuf,
+}
+
+impl DirectoryProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    async fn try_open(&self, key: &[u8; 32]) -> std::io::Result<PreimageStream<'static>> {
+        let file = tokio::fs::File::open(self.dir.join(hex::encode(key))).await?;
+        Ok(PreimageStream {
+            len: file.metadata().await?.len(),
+            reader: Box::new(file),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncPreimageProvider for DirectoryProvider {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        match tokio::fs::read(self.dir.join(hex::encode(key))).await {
+            Ok(data) => Some(data),
+            Err(e) => log_read_error(key, e),
+        }
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        match self.try_open(key).await {
+            Ok(preimage) => Some(preimage),
+            Err(e) => log_read_error(key, e),
+        }
+    }
+}
+
+fn log_read_error<T>(key: &[u8; 32], e: std::io::Error) -> Option<T> {
+    if e.kind() != ErrorKind::NotFound {
+        warn!("Unable to read preimage 0x{}: {}", hex::encode(key), e);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_preimages_are_streamed_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        std::fs::write(dir.path().join(hex::encode([1; 32])), &data).unwrap();
+        let provider = DirectoryProvider::new(dir.path());
+
+        let mut preimage = provider.open(&[1; 32]).await.unwrap();
+        assert_eq!(preimage.len, data.len() as u64);
+        let mut streamed = Vec::new();
+        tokio::io::copy(&mut preimage.reader, &mut streamed)
+            .await
+            .unwrap();
+        assert_eq!(streamed, data);
+
+        assert_eq!(provider.get(&[1; 32]).await, Some(data));
+        assert!(provider.open(&[2; 32]).await.is_none());
+        assert_eq!(provider.get(&[2; 32]).await, None);
+    }
+}
@@ -0,0 +1,102 @@
+use super::AsyncPreimageProvider;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::warn;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Reads and writes preimages in the op-program on-disk key-value format
+///
+/// Each preimage is stored in its own file named by the `0x` prefixed lowercase hex key with a `.txt` extension,
+/// containing the hex encoded value. Files are read as they are requested so large directories are not loaded up front.
+///
+/// # Examples
+/// ```no_run
+/// use preimage_server::preimage_provider::{AsyncPreimageProvider, DiskKvProvider};
+///
+/// # async fn example() {
+/// let provider = DiskKvProvider::new("/tmp/op-program/preimages");
+/// let data = provider.get(&[2; 32]).await;
+/// # }
+/// ```
+pub struct DiskKvProvider {
+    dir: PathBuf,
+}
+
+impl DiskKvProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Write a preimage, replacing any existing value. The directory must already exist
+    ///
+    /// The value is written to a temporary file first so readers never see a partially written preimage.
+    pub fn put(&self, key: &[u8; 32], value: &[u8]) -> Result<()> {
+        let path = self.path(key);
+        let tmp = path.with_extension("txt.tmp");
+        std::fs::write(&tmp, hex::encode(value))
+            .with_context(|| format!("Unable to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+        Ok(())
+    }
+
+    fn path(&self, key: &[u8; 32]) -> PathBuf {
+        // op-program names files with `common.Hash.String()`, which is `0x` prefixed
+        self.dir.join(format!("0x{}.txt", hex::encode(key)))
+    }
+
+    /// The preimage stored at `path`, or `None` if there is no such file
+    async fn read(path: &Path) -> Result<Option<Vec<u8>>> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(hex::decode(contents.trim())?))
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncPreimageProvider for DiskKvProvider {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let path = self.path(key);
+        Self::read(&path).await.unwrap_or_else(|e| {
+            warn!("Unable to read preimage from {}: {}", path.display(), e);
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_matches_op_program_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = DiskKvProvider::new(dir.path());
+        let mut key = [0xab; 32];
+        key[0] = 2;
+
+        provider.put(&key, b"\x01\x02").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(format!("0x02{}.txt", "ab".repeat(31))))
+                .unwrap(),
+            "0102"
+        );
+        assert_eq!(provider.get(&key).await, Some(vec![1, 2]));
+        assert_eq!(provider.get(&[3; 32]).await, None);
+
+        // files written by op-program are read as well
+        std::fs::write(provider.path(&[4; 32]), "deadbeef").unwrap();
+        assert_eq!(
+            provider.get(&[4; 32]).await,
+            Some(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+
+        // unreadable values are misses rather than errors
+        std::fs::write(provider.path(&[5; 32]), "not hex").unwrap();
+        assert_eq!(provider.get(&[5; 32]).await, None);
+    }
+}
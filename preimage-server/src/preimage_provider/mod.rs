@@ -0,0 +1,177 @@
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Cursor;
+use tokio::io::AsyncRead;
+
+mod archive;
+mod beacon;
+mod directory;
+mod disk_kv;
+#[cfg(test)]
+mod mock_http;
+mod routing;
+mod rpc;
+mod ssz;
+mod verifying;
+
+pub use archive::{is_archive, write_archive, ArchiveProvider};
+pub use beacon::{BeaconApiProvider, BeaconHintHandler};
+pub use directory::DirectoryProvider;
+pub use disk_kv::DiskKvProvider;
+pub use routing::RoutingProvider;
+pub use rpc::{KeccakRpcProvider, RpcHintHandler};
+pub use verifying::{key_matches, mismatched_keys, VerifyingProvider};
+
+pub trait PreimageProvider {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>>;
+
+    /// Open a preimage to be streamed to the guest
+    ///
+    /// Providers that can read a preimage in parts, e.g. from a file or memory map, should override this so
+    /// large preimages are not held in memory while they are served.
+    fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        self.get(key).map(PreimageStream::from)
+    }
+}
+
+/// A preimage of a known length that is read as it is served
+pub struct PreimageStream<'a> {
+    pub len: u64,
+    pub reader: Box<dyn AsyncRead + Unpin + 'a>,
+}
+
+impl From<Vec<u8>> for PreimageStream<'_> {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            len: data.len() as u64,
+            reader: Box::new(Cursor::new(data)),
+        }
+    }
+}
+
+/// A provider that needs to perform IO, e.g. reading from disk or making RPC calls, to retrieve preimages
+///
+/// The server awaits each request so other work such as handling hints is not blocked while the IO completes.
+/// Every [`PreimageProvider`] is also an `AsyncPreimageProvider`.
+#[async_trait(?Send)]
+pub trait AsyncPreimageProvider {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>>;
+
+    /// Open a preimage to be streamed to the guest. See [`PreimageProvider::open`]
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        self.get(key).await.map(PreimageStream::from)
+    }
+}
+
+#[async_trait(?Send)]
+impl<P: PreimageProvider + ?Sized> AsyncPreimageProvider for P {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        PreimageProvider::get(self, key)
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        PreimageProvider::open(self, key)
+    }
+}
+
+/// A provider that preimages can be added to while the server is running, e.g. by a `HintHandler`
+pub trait PreimageStore: PreimageProvider {
+    fn insert(&mut self, key: [u8; 32], value: Vec<u8>);
+
+    fn contains(&self, key: &[u8; 32]) -> bool;
+}
+
+impl<P: PreimageProvider + ?Sized> PreimageProvider for &P {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        (**self).get(key)
+    }
+
+    fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        (**self).open(key)
+    }
+}
+
+/// Allows a store shared with a `HintHandler` to be served at the same time
+impl<P: PreimageProvider + ?Sized> PreimageProvider for RefCell<P> {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        self.borrow().get(key)
+    }
+}
+
+/// Allows providers chosen at runtime to be wrapped by other providers
+#[async_trait(?Send)]
+impl AsyncPreimageProvider for Box<dyn AsyncPreimageProvider + '_> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        (**self).get(key).await
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        (**self).open(key).await
+    }
+}
+
+/// A provider that may not be configured serves nothing
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider> AsyncPreimageProvider for Option<P> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        match self {
+            Some(provider) => provider.get(key).await,
+            None => None,
+        }
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        match self {
+            Some(provider) => provider.open(key).await,
+            None => None,
+        }
+    }
+}
+
+/// Serves preimages from `primary` and consults `fallback` for any keys it does not have
+pub struct FallbackProvider<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P: AsyncPreimageProvider, F: AsyncPreimageProvider> FallbackProvider<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider, F: AsyncPreimageProvider> AsyncPreimageProvider
+    for FallbackProvider<P, F>
+{
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        match self.primary.get(key).await {
+            Some(data) => Some(data),
+            None => self.fallback.get(key).await,
+        }
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        match self.primary.open(key).await {
+            Some(preimage) => Some(preimage),
+            None => self.fallback.open(key).await,
+        }
+    }
+}
+
+impl PreimageProvider for HashMap<[u8; 32], Vec<u8>> {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        self.get(key).cloned()
+    }
+}
+
+impl PreimageStore for HashMap<[u8; 32], Vec<u8>> {
+    fn insert(&mut self, key: [u8; 32], value: Vec<u8>) {
+        HashMap::insert(self, key, value);
+    }
+
+    fn contains(&self, key: &[u8; 32]) -> bool {
+        self.contains_key(key)
+    }
+}
@@ -0,0 +1,113 @@
+use super::{AsyncPreimageProvider, PreimageStream};
+use async_trait::async_trait;
+use cannon_io::oracle::KeyType;
+use std::collections::HashMap;
+
+/// Dispatches each request to a sub-provider based on the type byte of the key
+///
+/// This allows different kinds of data to be served from different sources. e.g. local keys from
+/// configuration, keccak256 preimages from one store and sha256 beacon chain data from another.
+///
+/// # Examples
+/// ```no_run
+/// use cannon_io::oracle::KeyType;
+/// use preimage_server::preimage_provider::{DirectoryProvider, RoutingProvider};
+/// use std::collections::HashMap;
+///
+/// let local_inputs = HashMap::from([([1; 32], b"input".to_vec())]);
+/// let provider = RoutingProvider::new()
+///     .with_route(KeyType::Local, local_inputs)
+///     .with_route(KeyType::Keccak256, DirectoryProvider::new("keccak"))
+///     .with_route(KeyType::Sha256, DirectoryProvider::new("beacon"));
+/// ```
+#[derive(Default)]
+pub struct RoutingProvider {
+    routes: HashMap<u8, Box<dyn AsyncPreimageProvider>>,
+    fallback: Option<Box<dyn AsyncPreimageProvider>>,
+}
+
+impl RoutingProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve keys of the given type from `provider`. Replaces any existing route for that type
+    pub fn with_route(
+        self,
+        key_type: KeyType,
+        provider: impl AsyncPreimageProvider + 'static,
+    ) -> Self {
+        self.with_type_byte_route(key_type as u8, provider)
+    }
+
+    /// Serve keys with the given type byte from `provider`. Useful for key types not known to `KeyType`
+    pub fn with_type_byte_route(
+        mut self,
+        type_byte: u8,
+        provider: impl AsyncPreimageProvider + 'static,
+    ) -> Self {
+        self.routes.insert(type_byte, Box::new(provider));
+        self
+    }
+
+    /// Serve keys of any type without a route from `provider`
+    pub fn with_fallback(mut self, provider: impl AsyncPreimageProvider + 'static) -> Self {
+        self.fallback = Some(Box::new(provider));
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncPreimageProvider for RoutingProvider {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        match self.routes.get(&key[0]).or(self.fallback.as_ref()) {
+            Some(provider) => provider.get(key).await,
+            None => None,
+        }
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        match self.routes.get(&key[0]).or(self.fallback.as_ref()) {
+            Some(provider) => provider.open(key).await,
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(key: [u8; 32], value: &[u8]) -> HashMap<[u8; 32], Vec<u8>> {
+        HashMap::from([(key, value.to_vec())])
+    }
+
+    #[tokio::test]
+    async fn test_routes_by_key_type() {
+        let mut local_key = [0; 32];
+        local_key[0] = KeyType::Local as u8;
+        let mut keccak_key = [0; 32];
+        keccak_key[0] = KeyType::Keccak256 as u8;
+        let mut sha_key = [0; 32];
+        sha_key[0] = KeyType::Sha256 as u8;
+
+        let provider = RoutingProvider::new()
+            .with_route(KeyType::Local, store(local_key, b"local"))
+            // a keccak key in the wrong store is not found
+            .with_route(KeyType::Keccak256, store(sha_key, b"misplaced"))
+            .with_fallback(store(sha_key, b"sha"));
+
+        assert_eq!(provider.get(&local_key).await, Some(b"local".to_vec()));
+        assert_eq!(provider.get(&keccak_key).await, None);
+        assert_eq!(provider.get(&sha_key).await, Some(b"sha".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_type_without_fallback() {
+        let key = [4; 32];
+        let provider = RoutingProvider::new().with_route(KeyType::Generic, store(key, b"x"));
+        assert_eq!(provider.get(&key).await, None);
+        let provider = provider.with_type_byte_route(4, store(key, b"x"));
+        assert_eq!(provider.get(&key).await, Some(b"x".to_vec()));
+    }
+}
@@ -0,0 +1,334 @@
+use super::AsyncPreimageProvider;
+use crate::hint_handler::{Hint, HintHandler};
+use crate::preimage_provider::PreimageStore;
+use crate::raw::keccak256;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use cannon_io::oracle::KeyType;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Prefix of contract code entries in the geth database
+const CODE_PREFIX: u8 = b'c';
+
+/// How the preimage of a hinted hash can be retrieved from the execution client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lookup {
+    /// RLP encoded block header, retrieved with `debug_getRawHeader`
+    Header([u8; 32]),
+    /// Trie node or other database entry, retrieved with `debug_dbGet` or `debug_preimage`
+    DbEntry([u8; 32]),
+    /// Contract code, stored under a prefixed key in the database
+    Code([u8; 32]),
+}
+
+impl Lookup {
+    /// The lookup for a hint in the format sent by the fault proof program, e.g. `l1-block-header 0x...`
+    fn from_hint(hint: &Hint) -> Option<Self> {
+        let hash: [u8; 32] = hint.data.as_slice().try_into().ok()?;
+        let (_, kind) = hint.hint_type.split_once('-')?;
+        match kind {
+            "block-header" => Some(Lookup::Header(hash)),
+            "state-node" => Some(Lookup::DbEntry(hash)),
+            "code" => Some(Lookup::Code(hash)),
+            _ => None,
+        }
+    }
+
+    fn hash(&self) -> [u8; 32] {
+        match self {
+            Lookup::Header(hash) | Lookup::DbEntry(hash) | Lookup::Code(hash) => *hash,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RpcState {
+    hinted: HashMap<[u8; 32], Lookup>,
+    cache: HashMap<[u8; 32], Vec<u8>>,
+}
+
+/// Serves keccak256 preimages by querying an Ethereum execution client over JSON-RPC
+///
+/// A keccak256 key only contains the last 31 bytes of the hash so the full hash, and the kind of data it
+/// refers to, must first be provided by a hint. Hints are received by the handler returned from
+/// [`hint_handler`](Self::hint_handler) and the data is fetched when the guest requests the key.
+/// The data is checked to hash to the hinted value and cached in memory and, optionally, in a directory
+/// with one file per key that can be loaded by the preimage server later on.
+///
+/// The client must support the geth `debug` namespace.
+///
+/// # Examples
+/// ```no_run
+/// use preimage_server::preimage_provider::KeccakRpcProvider;
+///
+/// let provider = KeccakRpcProvider::new("http://localhost:8545").with_cache_dir("./preimages");
+/// let handler = provider.hint_handler();
+/// ```
+pub struct KeccakRpcProvider {
+    client: reqwest::Client,
+    url: String,
+    cache_dir: Option<PathBuf>,
+    state: Rc<RefCell<RpcState>>,
+}
+
+impl KeccakRpcProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            cache_dir: None,
+            state: Rc::default(),
+        }
+    }
+
+    /// Also write each retrieved preimage to a file in `dir` named with the hex encoded key
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Handler that must receive the guest hints so the provider knows what to fetch
+    pub fn hint_handler(&self) -> RpcHintHandler {
+        RpcHintHandler {
+            state: self.state.clone(),
+        }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Vec<u8>> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            bail!("{} failed: {}", method, error);
+        }
+        let result = response
+            .get("result")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("{} returned no data", method))?;
+        Ok(hex::decode(result.trim_start_matches("0x"))?)
+    }
+
+    async fn fetch(&self, lookup: Lookup) -> Result<Vec<u8>> {
+        let hash = format!("0x{}", hex::encode(lookup.hash()));
+        let data = match lookup {
+            Lookup::Header(_) => self.call("debug_getRawHeader", json!([hash])).await?,
+            Lookup::DbEntry(_) => match self.call("debug_dbGet", json!([hash])).await {
+                Ok(data) => data,
+                Err(e) => {
+                    debug!("{}, trying debug_preimage", e);
+                    self.call("debug_preimage", json!([hash])).await?
+                }
+            },
+            Lookup::Code(code_hash) => {
+                let mut key = vec![CODE_PREFIX];
+                key.extend_from_slice(&code_hash);
+                let key = format!("0x{}", hex::encode(key));
+                match self.call("debug_dbGet", json!([key])).await {
+                    Ok(data) => data,
+                    // older databases store code without a prefix
+                    Err(_) => self.call("debug_dbGet", json!([hash])).await?,
+                }
+            }
+        };
+        if keccak256(&data) != lookup.hash() {
+            bail!("Data returned for {} does not match its hash", hash);
+        }
+        Ok(data)
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncPreimageProvider for KeccakRpcProvider {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        if key[0] != KeyType::Keccak256 as u8 {
+            return None;
+        }
+        if let Some(data) = self.state.borrow().cache.get(key) {
+            return Some(data.clone());
+        }
+        let lookup = match self.state.borrow().hinted.get(key) {
+            Some(lookup) => *lookup,
+            None => {
+                debug!("No hint received for key 0x{}", hex::encode(key));
+                return None;
+            }
+        };
+
+        let data = match self.fetch(lookup).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Unable to retrieve preimage from RPC: {}", e);
+                return None;
+            }
+        };
+        if let Some(dir) = &self.cache_dir {
+            if let Err(e) = write_cache_file(dir, key, &data).await {
+                warn!("Unable to write preimage to cache directory: {}", e);
+            }
+        }
+        self.state.borrow_mut().cache.insert(*key, data.clone());
+        Some(data)
+    }
+}
+
+/// Write a preimage to the cache directory. The data is written to a temporary file first so a run that is killed
+/// part way through never leaves a truncated preimage under its key
+async fn write_cache_file(dir: &Path, key: &[u8; 32], data: &[u8]) -> std::io::Result<()> {
+    let path = dir.join(hex::encode(key));
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, &path).await
+}
+
+/// Records the hashes hinted by the guest for a [`KeccakRpcProvider`]
+pub struct RpcHintHandler {
+    state: Rc<RefCell<RpcState>>,
+}
+
+impl HintHandler for RpcHintHandler {
+    fn handle_hint(&mut self, hint: &Hint, _preimages: &mut dyn PreimageStore) -> Result<()> {
+        // other hints are for other handlers
+        if let Some(lookup) = Lookup::from_hint(hint) {
+            let mut key = lookup.hash();
+            key[0] = KeyType::Keccak256 as u8;
+            self.state.borrow_mut().hinted.insert(key, lookup);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preimage_provider::mock_http::{MockHttpServer, Request};
+
+    fn key_for(data: &[u8]) -> ([u8; 32], [u8; 32]) {
+        let hash = keccak256(data);
+        let mut key = hash;
+        key[0] = KeyType::Keccak256 as u8;
+        (hash, key)
+    }
+
+    /// Responds to every call with the given data, or an error for methods not in the list
+    async fn rpc_server(methods: &'static [(&'static str, &'static [u8])]) -> MockHttpServer {
+        MockHttpServer::start(|request: &Request| {
+            let request: Value = serde_json::from_slice(&request.body).unwrap();
+            let response = match methods.iter().find(|(m, _)| request["method"] == *m) {
+                Some((_, data)) => json!({"jsonrpc": "2.0", "id": 1, "result": format!("0x{}", hex::encode(data))}),
+                None => json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "not found"}}),
+            };
+            (200, response.to_string().into_bytes())
+        })
+        .await
+    }
+
+    fn hint(provider: &KeccakRpcProvider, hint_type: &str, hash: [u8; 32]) {
+        let hint = Hint {
+            hint_type: hint_type.to_string(),
+            data: hash.to_vec(),
+        };
+        provider
+            .hint_handler()
+            .handle_hint(&hint, &mut HashMap::new())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hinted_header_is_fetched_and_cached() {
+        let server = rpc_server(&[("debug_getRawHeader", b"header rlp")]).await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let provider = KeccakRpcProvider::new(&server.url).with_cache_dir(cache_dir.path());
+        let (hash, key) = key_for(b"header rlp");
+
+        // nothing is fetched without a hint
+        assert_eq!(provider.get(&key).await, None);
+        assert!(server.requests().is_empty());
+
+        hint(&provider, "l1-block-header", hash);
+        assert_eq!(provider.get(&key).await, Some(b"header rlp".to_vec()));
+        assert_eq!(provider.get(&key).await, Some(b"header rlp".to_vec()));
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(server.requests()[0].method, "POST");
+        let request: Value = serde_json::from_slice(&server.requests()[0].body).unwrap();
+        assert_eq!(
+            request["params"],
+            json!([format!("0x{}", hex::encode(hash))])
+        );
+        assert_eq!(
+            std::fs::read(cache_dir.path().join(hex::encode(key))).unwrap(),
+            b"header rlp"
+        );
+        // the temporary file the preimage was written to has been renamed into place
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_state_node_falls_back_to_debug_preimage() {
+        let server = rpc_server(&[("debug_preimage", b"trie node")]).await;
+        let provider = KeccakRpcProvider::new(&server.url);
+        let (hash, key) = key_for(b"trie node");
+
+        hint(&provider, "l2-state-node", hash);
+        assert_eq!(provider.get(&key).await, Some(b"trie node".to_vec()));
+        assert_eq!(server.requests().len(), 2);
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.method == "POST"));
+    }
+
+    #[tokio::test]
+    async fn test_code_falls_back_to_unprefixed_key() {
+        let (hash, key) = key_for(b"code");
+        let unprefixed = format!("0x{}", hex::encode(hash));
+        let server = MockHttpServer::start(move |request: &Request| {
+            let request: Value = serde_json::from_slice(&request.body).unwrap();
+            let response = if request["params"] == json!([unprefixed]) {
+                json!({"jsonrpc": "2.0", "id": 1, "result": format!("0x{}", hex::encode(b"code"))})
+            } else {
+                json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32000, "message": "not found"}})
+            };
+            (200, response.to_string().into_bytes())
+        })
+        .await;
+        let provider = KeccakRpcProvider::new(&server.url);
+
+        hint(&provider, "l2-code", hash);
+        assert_eq!(provider.get(&key).await, Some(b"code".to_vec()));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let first: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(first["method"], "debug_dbGet");
+        assert_eq!(
+            first["params"],
+            json!([format!("0x{:02x}{}", CODE_PREFIX, hex::encode(hash))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_data_not_matching_hash_is_rejected() {
+        let server = rpc_server(&[("debug_dbGet", b"wrong code")]).await;
+        let provider = KeccakRpcProvider::new(&server.url);
+        let (hash, key) = key_for(b"code");
+
+        hint(&provider, "l2-code", hash);
+        assert_eq!(provider.get(&key).await, None);
+    }
+}
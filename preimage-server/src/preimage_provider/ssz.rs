@@ -0,0 +1,362 @@
+//! Schema driven SSZ merkleization that records every node of the resulting tree
+//!
+//! Only what is needed to compute `hash_tree_root` is implemented. The SSZ bytes are split according to the
+//! schema but values are not otherwise validated.
+
+use anyhow::{bail, ensure, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+const CHUNK_SIZE: usize = 32;
+
+type Chunk = [u8; 32];
+
+/// The preimages of all internal nodes of a tree, keyed by their sha256 hash
+pub type Nodes = HashMap<[u8; 32], [u8; 64]>;
+
+/// The shape of an SSZ type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ssz {
+    /// Unsigned integer of the given number of bytes
+    Uint(usize),
+    Bool,
+    Vector(Box<Ssz>, usize),
+    List(Box<Ssz>, usize),
+    Bitvector(usize),
+    Bitlist(usize),
+    Container(Vec<Ssz>),
+}
+
+impl Ssz {
+    pub fn vector(element: Ssz, length: usize) -> Self {
+        Ssz::Vector(Box::new(element), length)
+    }
+
+    pub fn list(element: Ssz, limit: usize) -> Self {
+        Ssz::List(Box::new(element), limit)
+    }
+
+    pub fn byte_vector(length: usize) -> Self {
+        Self::vector(Ssz::Uint(1), length)
+    }
+
+    pub fn byte_list(limit: usize) -> Self {
+        Self::list(Ssz::Uint(1), limit)
+    }
+
+    fn is_basic(&self) -> bool {
+        matches!(self, Ssz::Uint(_) | Ssz::Bool)
+    }
+
+    /// Serialized size of the type, or `None` if it is variable size
+    fn fixed_size(&self) -> Option<usize> {
+        match self {
+            Ssz::Uint(size) => Some(*size),
+            Ssz::Bool => Some(1),
+            Ssz::Vector(element, length) => element.fixed_size().map(|size| size * length),
+            Ssz::Bitvector(length) => Some((length + 7) / 8),
+            Ssz::List(..) | Ssz::Bitlist(_) => None,
+            Ssz::Container(fields) => fields.iter().map(Ssz::fixed_size).sum(),
+        }
+    }
+
+    /// Compute the `hash_tree_root` of the SSZ encoded `bytes`, adding every internal node to `nodes`
+    pub fn hash_tree_root(&self, bytes: &[u8], nodes: &mut Nodes) -> Result<[u8; 32]> {
+        if let Some(size) = self.fixed_size() {
+            ensure!(
+                bytes.len() == size,
+                "expected {} bytes for {:?}, got {}",
+                size,
+                self,
+                bytes.len()
+            );
+        }
+        match self {
+            Ssz::Uint(_) | Ssz::Bool => Ok(pack(bytes)[0]),
+            Ssz::Vector(element, length) if element.is_basic() => {
+                let limit = chunk_count(length * element.fixed_size().unwrap());
+                Ok(merkleize(pack(bytes), limit, nodes))
+            }
+            Ssz::Vector(element, length) => {
+                let roots = split_sequence(element, bytes)?
+                    .into_iter()
+                    .map(|e| element.hash_tree_root(e, nodes))
+                    .collect::<Result<Vec<_>>>()?;
+                ensure!(roots.len() == *length, "vector length mismatch");
+                Ok(merkleize(roots, *length, nodes))
+            }
+            Ssz::List(element, limit) if element.is_basic() => {
+                let size = element.fixed_size().unwrap();
+                ensure!(
+                    bytes.len() % size == 0,
+                    "list is not a whole number of elements"
+                );
+                let length = bytes.len() / size;
+                ensure!(length <= *limit, "list longer than its limit");
+                let root = merkleize(pack(bytes), chunk_count(limit * size), nodes);
+                Ok(mix_in_length(root, length, nodes))
+            }
+            Ssz::List(element, limit) => {
+                let roots = split_sequence(element, bytes)?
+                    .into_iter()
+                    .map(|e| element.hash_tree_root(e, nodes))
+                    .collect::<Result<Vec<_>>>()?;
+                ensure!(roots.len() <= *limit, "list longer than its limit");
+                let length = roots.len();
+                let root = merkleize(roots, *limit, nodes);
+                Ok(mix_in_length(root, length, nodes))
+            }
+            Ssz::Bitvector(length) => {
+                Ok(merkleize(pack(bytes), chunk_count((length + 7) / 8), nodes))
+            }
+            Ssz::Bitlist(limit) => {
+                let (last, _) = bytes
+                    .split_last()
+                    .filter(|(last, _)| **last != 0)
+                    .ok_or_else(|| anyhow::anyhow!("bitlist is missing its delimiter bit"))?;
+                let delimiter = 7 - last.leading_zeros() as usize;
+                let length = (bytes.len() - 1) * 8 + delimiter;
+                ensure!(length <= *limit, "bitlist longer than its limit");
+                let mut bits = bytes.to_vec();
+                *bits.last_mut().unwrap() ^= 1 << delimiter;
+                let root = merkleize(pack(&bits), chunk_count((limit + 7) / 8), nodes);
+                Ok(mix_in_length(root, length, nodes))
+            }
+            Ssz::Container(fields) => {
+                let roots = split_container(fields, bytes)?
+                    .into_iter()
+                    .zip(fields)
+                    .map(|(field_bytes, field)| field.hash_tree_root(field_bytes, nodes))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(merkleize(roots, fields.len(), nodes))
+            }
+        }
+    }
+}
+
+fn read_offset(bytes: &[u8], at: usize) -> Result<usize> {
+    ensure!(bytes.len() >= at + 4, "offset out of range");
+    Ok(u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize)
+}
+
+/// Split the variable size parts of `bytes` at the given offsets
+fn split_at_offsets<'a>(bytes: &'a [u8], offsets: &[usize]) -> Result<Vec<&'a [u8]>> {
+    let mut parts = Vec::with_capacity(offsets.len());
+    for (i, start) in offsets.iter().enumerate() {
+        let end = offsets.get(i + 1).copied().unwrap_or(bytes.len());
+        ensure!(start <= &end && end <= bytes.len(), "invalid offset");
+        parts.push(&bytes[*start..end]);
+    }
+    Ok(parts)
+}
+
+/// Split the elements of a vector or list
+fn split_sequence<'a>(element: &Ssz, bytes: &'a [u8]) -> Result<Vec<&'a [u8]>> {
+    if let Some(size) = element.fixed_size() {
+        ensure!(
+            size > 0 && bytes.len() % size == 0,
+            "sequence is not a whole number of elements"
+        );
+        return Ok(bytes.chunks(size).collect());
+    }
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let first = read_offset(bytes, 0)?;
+    ensure!(first % 4 == 0 && first > 0, "invalid first offset");
+    let offsets = (0..first / 4)
+        .map(|i| read_offset(bytes, i * 4))
+        .collect::<Result<Vec<_>>>()?;
+    split_at_offsets(bytes, &offsets)
+}
+
+/// Split the fields of a container
+fn split_container<'a>(fields: &[Ssz], bytes: &'a [u8]) -> Result<Vec<&'a [u8]>> {
+    let mut parts = Vec::with_capacity(fields.len());
+    let mut offsets = Vec::new();
+    let mut position = 0;
+    for field in fields {
+        match field.fixed_size() {
+            Some(size) => {
+                ensure!(bytes.len() >= position + size, "container too short");
+                parts.push(Some(&bytes[position..position + size]));
+                position += size;
+            }
+            None => {
+                offsets.push(read_offset(bytes, position)?);
+                parts.push(None);
+                position += 4;
+            }
+        }
+    }
+    if let Some(first) = offsets.first() {
+        if *first != position {
+            bail!("first offset does not point to the end of the fixed size part");
+        }
+    } else {
+        ensure!(position == bytes.len(), "container has trailing bytes");
+    }
+    let mut variable = split_at_offsets(bytes, &offsets)?.into_iter();
+    Ok(parts
+        .into_iter()
+        .map(|part| part.unwrap_or_else(|| variable.next().unwrap()))
+        .collect())
+}
+
+/// Number of chunks needed to hold `size` bytes
+fn chunk_count(size: usize) -> usize {
+    (size + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+/// Pack bytes into zero padded chunks
+fn pack(bytes: &[u8]) -> Vec<Chunk> {
+    bytes
+        .chunks(CHUNK_SIZE)
+        .map(|part| {
+            let mut chunk = [0; CHUNK_SIZE];
+            chunk[..part.len()].copy_from_slice(part);
+            chunk
+        })
+        .collect()
+}
+
+fn hash_pair(left: &Chunk, right: &Chunk, nodes: &mut Nodes) -> Chunk {
+    let mut preimage = [0; 64];
+    preimage[..32].copy_from_slice(left);
+    preimage[32..].copy_from_slice(right);
+    let hash: Chunk = Sha256::digest(preimage).into();
+    nodes.insert(hash, preimage);
+    hash
+}
+
+/// Merkleize `chunks` as a tree with room for `limit` chunks, padding with zero subtrees
+fn merkleize(mut chunks: Vec<Chunk>, limit: usize, nodes: &mut Nodes) -> Chunk {
+    let depth = limit.max(1).next_power_of_two().trailing_zeros();
+    let mut zero = [0; CHUNK_SIZE];
+    if chunks.is_empty() {
+        chunks.push(zero);
+    }
+    for _ in 0..depth {
+        if chunks.len() % 2 == 1 {
+            chunks.push(zero);
+        }
+        chunks = chunks
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1], nodes))
+            .collect();
+        // the zero subtrees are part of the tree so their nodes are recorded too
+        zero = hash_pair(&zero, &zero, nodes);
+    }
+    chunks[0]
+}
+
+fn mix_in_length(root: Chunk, length: usize, nodes: &mut Nodes) -> Chunk {
+    let mut length_chunk = [0; CHUNK_SIZE];
+    length_chunk[..8].copy_from_slice(&(length as u64).to_le_bytes());
+    hash_pair(&root, &length_chunk, nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> Chunk {
+        Sha256::digest(data).into()
+    }
+
+    fn concat(left: &[u8], right: &[u8]) -> Vec<u8> {
+        [left, right].concat()
+    }
+
+    #[test]
+    fn test_container_of_basic_fields() {
+        let schema = Ssz::Container(vec![Ssz::Uint(8), Ssz::Bool, Ssz::byte_vector(32)]);
+        let bytes = [&5_u64.to_le_bytes()[..], &[1], &[0xaa; 32]].concat();
+        let mut nodes = Nodes::new();
+        let root = schema.hash_tree_root(&bytes, &mut nodes).unwrap();
+
+        let mut a = [0; 32];
+        a[0] = 5;
+        let mut b = [0; 32];
+        b[0] = 1;
+        let left = sha256(&concat(&a, &b));
+        let right = sha256(&concat(&[0xaa; 32], &[0; 32]));
+        assert_eq!(root, sha256(&concat(&left, &right)));
+        assert_eq!(nodes[&root][..32], left);
+        assert_eq!(nodes[&left][32..], b);
+    }
+
+    #[test]
+    fn test_list_mixes_in_length() {
+        let schema = Ssz::list(Ssz::Uint(8), 8);
+        let bytes = [1_u64.to_le_bytes(), 2_u64.to_le_bytes()].concat();
+        let mut nodes = Nodes::new();
+        let root = schema.hash_tree_root(&bytes, &mut nodes).unwrap();
+
+        // 8 u64 values fit into 2 chunks
+        let mut chunk = [0; 32];
+        chunk[..16].copy_from_slice(&bytes);
+        let data_root = sha256(&concat(&chunk, &[0; 32]));
+        let mut length = [0; 32];
+        length[0] = 2;
+        assert_eq!(root, sha256(&concat(&data_root, &length)));
+    }
+
+    #[test]
+    fn test_variable_size_fields_and_bitlist() {
+        let schema = Ssz::Container(vec![Ssz::Uint(2), Ssz::byte_list(4), Ssz::Bitlist(16)]);
+        // fixed part: u16, offset, offset. The bitlist has 3 bits set followed by the delimiter
+        let bytes = [
+            &[7, 0][..],
+            &10_u32.to_le_bytes(),
+            &12_u32.to_le_bytes(),
+            &[0xab, 0xcd],
+            &[0b1111],
+        ]
+        .concat();
+        let mut nodes = Nodes::new();
+        let root = schema.hash_tree_root(&bytes, &mut nodes).unwrap();
+
+        let mut list = [0; 32];
+        list[..2].copy_from_slice(&[0xab, 0xcd]);
+        let mut list_length = [0; 32];
+        list_length[0] = 2;
+        let mut bits = [0; 32];
+        bits[0] = 0b111;
+        let mut bits_length = [0; 32];
+        bits_length[0] = 3;
+        let mut number = [0; 32];
+        number[0] = 7;
+        let fields = [
+            number,
+            sha256(&concat(&list, &list_length)),
+            sha256(&concat(&bits, &bits_length)),
+            [0; 32],
+        ];
+        let expected = sha256(&concat(
+            &sha256(&concat(&fields[0], &fields[1])),
+            &sha256(&concat(&fields[2], &fields[3])),
+        ));
+        assert_eq!(root, expected);
+    }
+
+    #[test]
+    fn test_zero_subtrees_are_recorded() {
+        let schema = Ssz::list(Ssz::byte_vector(32), 4);
+        let mut nodes = Nodes::new();
+        schema.hash_tree_root(&[], &mut nodes).unwrap();
+        let zero_1 = sha256(&[0; 64]);
+        assert_eq!(nodes[&zero_1], [0; 64]);
+        assert!(nodes.contains_key(&sha256(&concat(&zero_1, &zero_1))));
+    }
+
+    #[test]
+    fn test_invalid_encoding_is_rejected() {
+        let mut nodes = Nodes::new();
+        assert!(Ssz::Uint(8).hash_tree_root(&[0; 4], &mut nodes).is_err());
+        assert!(Ssz::Bitlist(8).hash_tree_root(&[0], &mut nodes).is_err());
+        assert!(Ssz::list(Ssz::byte_list(4), 4)
+            .hash_tree_root(&[3, 0, 0, 0], &mut nodes)
+            .is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use super::AsyncPreimageProvider;
+use crate::error::key_type_name;
+use crate::raw::{keccak256, sha256};
+use async_trait::async_trait;
+use cannon_io::oracle::KeyType;
+use log::error;
+
+/// Check that the value of a keccak256 or sha256 key hashes to the key
+///
+/// Other key types are not hashes so always match.
+pub fn key_matches(key: &[u8; 32], data: &[u8]) -> bool {
+    let hash = match KeyType::try_from(key[0]) {
+        Ok(KeyType::Keccak256) => keccak256(data),
+        Ok(KeyType::Sha256) => sha256(data),
+        _ => return true,
+    };
+    // the first byte of the hash is replaced by the type byte
+    hash[1..] == key[1..]
+}
+
+/// All keys whose value does not match, sorted so they are reported in a stable order
+pub fn mismatched_keys<'a>(
+    preimages: impl IntoIterator<Item = (&'a [u8; 32], &'a Vec<u8>)>,
+) -> Vec<[u8; 32]> {
+    let mut mismatched: Vec<[u8; 32]> = preimages
+        .into_iter()
+        .filter(|(key, data)| !key_matches(key, data))
+        .map(|(key, _)| *key)
+        .collect();
+    mismatched.sort();
+    mismatched
+}
+
+/// Checks every preimage served by the inner provider matches its key
+///
+/// Preimages that do not match are logged and not served. Every preimage is read fully to check it so none are
+/// streamed from disk.
+pub struct VerifyingProvider<P> {
+    inner: P,
+}
+
+impl<P: AsyncPreimageProvider> VerifyingProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider> AsyncPreimageProvider for VerifyingProvider<P> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let data = self.inner.get(key).await?;
+        if !key_matches(key, &data) {
+            error!(
+                "Preimage for key 0x{} ({}) does not match its hash",
+                hex::encode(key),
+                key_type_name(key[0])
+            );
+            return None;
+        }
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cannon_io::oracle::PreimageKey;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_mismatches_are_found_and_not_served() {
+        let keccak_key: [u8; 32] = PreimageKey::new_keccak(keccak256(b"data")).into();
+        let sha_key: [u8; 32] = PreimageKey::new_sha256(sha256(b"data")).into();
+        let local_key: [u8; 32] = PreimageKey::new_local(&[1]).into();
+        let preimages = HashMap::from([
+            (keccak_key, b"data".to_vec()),
+            (sha_key, b"other data".to_vec()),
+            (local_key, b"anything".to_vec()),
+        ]);
+
+        assert_eq!(mismatched_keys(&preimages), [sha_key]);
+
+        let provider = VerifyingProvider::new(preimages);
+        assert_eq!(provider.get(&keccak_key).await, Some(b"data".to_vec()));
+        assert_eq!(provider.get(&sha_key).await, None);
+        assert_eq!(provider.get(&local_key).await, Some(b"anything".to_vec()));
+    }
+}
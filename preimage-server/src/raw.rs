@@ -0,0 +1,104 @@
+//! Serve arbitrary data files under keys computed from their contents
+//!
+//! Every file is served under both its keccak256 and its sha256 key. The type byte replaces the first byte of
+//! the hash exactly as `PreimageKey::new_keccak` and `PreimageKey::new_sha256` do in the guest.
+
+use anyhow::{Context, Result};
+use cannon_io::oracle::PreimageKey;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tiny_keccak::{Hasher, Keccak};
+
+/// A data file along with the keys it is served under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawPreimage {
+    pub path: PathBuf,
+    pub keccak256_key: [u8; 32],
+    pub sha256_key: [u8; 32],
+    pub data: Vec<u8>,
+}
+
+impl RawPreimage {
+    pub fn new(path: PathBuf, data: Vec<u8>) -> Self {
+        Self {
+            path,
+            keccak256_key: PreimageKey::new_keccak(keccak256(&data)).into(),
+            sha256_key: PreimageKey::new_sha256(sha256(&data)).into(),
+            data,
+        }
+    }
+}
+
+/// Load a single file, or every file in a directory sorted by name
+pub fn load(path: &Path) -> Result<Vec<RawPreimage>> {
+    let paths = if path.is_dir() {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(path)
+            .with_context(|| format!("Unable to read directory {}", path.display()))?
+        {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        paths
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let data = std::fs::read(&path)
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            Ok(RawPreimage::new(path, data))
+        })
+        .collect()
+}
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    let mut hash = [0; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_match_guest_key_construction() {
+        let preimage = RawPreimage::new("empty".into(), Vec::new());
+        // keccak256("") = c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470
+        assert_eq!(
+            hex::encode(preimage.keccak256_key),
+            "02d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        // sha256("") = e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855
+        assert_eq!(
+            hex::encode(preimage.sha256_key),
+            "81b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("b.bin"), b"second").unwrap();
+        std::fs::write(dir.path().join("a.bin"), b"first").unwrap();
+
+        let preimages = load(dir.path()).unwrap();
+        assert_eq!(preimages.len(), 2);
+        assert_eq!(preimages[0].path, dir.path().join("a.bin"));
+        assert_eq!(preimages[1].data, b"second");
+        assert_eq!(load(&dir.path().join("a.bin")).unwrap(), preimages[..1]);
+    }
+}
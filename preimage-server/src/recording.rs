@@ -0,0 +1,216 @@
+//! Record the hints and preimage requests of a run
+//!
+//! A recording can be written out as a log of every event in order and as a preimage store containing only the
+//! preimages the guest requested. The latter can be loaded by the preimage server to replay a run captured against
+//! a large or remote provider without it, or written in the op-program disk key-value format to share them with the
+//! Go tooling.
+
+use crate::hint_handler::{Hint, HintHandler};
+use crate::preimage_provider::{AsyncPreimageProvider, PreimageStore};
+use crate::store::{self, Format};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Something the guest did during a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A hint as it was sent, whether or not it could be decoded
+    Hint(Vec<u8>),
+    /// A preimage request and the size of the preimage served, if it was found
+    Request { key: [u8; 32], size: Option<usize> },
+}
+
+#[derive(Default)]
+struct RecordingState {
+    events: Vec<Event>,
+    preimages: HashMap<[u8; 32], Vec<u8>>,
+}
+
+/// Shared recording of a run. Wrap the provider and hint handler to record what passes through them
+#[derive(Clone, Default)]
+pub struct Recording {
+    state: Rc<RefCell<RecordingState>>,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record every request made to `provider`
+    pub fn provider<P: AsyncPreimageProvider>(&self, provider: P) -> RecordingProvider<P> {
+        RecordingProvider {
+            inner: provider,
+            recording: self.clone(),
+        }
+    }
+
+    /// Record every hint passed to `handler`
+    pub fn hint_handler<H: HintHandler>(&self, handler: H) -> RecordingHintHandler<H> {
+        RecordingHintHandler {
+            inner: handler,
+            recording: self.clone(),
+        }
+    }
+
+    /// All events so far, in order
+    pub fn events(&self) -> Vec<Event> {
+        self.state.borrow().events.clone()
+    }
+
+    /// Write all events in order as a JSON array
+    pub fn write_log(&self, path: &Path) -> Result<()> {
+        let events: Vec<Value> = self
+            .state
+            .borrow()
+            .events
+            .iter()
+            .map(|event| match event {
+                Event::Hint(raw) => {
+                    let mut event = json!({
+                        "type": "hint",
+                        "raw": format!("0x{}", hex::encode(raw)),
+                        "size": raw.len(),
+                    });
+                    if let Ok(hint) = Hint::decode(raw) {
+                        event["hint_type"] = json!(hint.hint_type);
+                        event["data"] = json!(format!("0x{}", hex::encode(&hint.data)));
+                    }
+                    event
+                }
+                Event::Request { key, size } => json!({
+                    "type": "request",
+                    "key": format!("0x{}", hex::encode(key)),
+                    "size": size,
+                }),
+            })
+            .collect();
+        std::fs::write(path, serde_json::to_string_pretty(&events)?)?;
+        Ok(())
+    }
+
+    /// Write the preimages that were served to a store in any of the formats the preimage server loads
+    pub fn write_preimages(&self, path: &Path, format: Format) -> Result<()> {
+        store::save(path, format, &self.state.borrow().preimages, false)
+    }
+}
+
+/// Records the requests made to the inner provider. Preimages are read fully so they can be exported
+pub struct RecordingProvider<P> {
+    inner: P,
+    recording: Recording,
+}
+
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider> AsyncPreimageProvider for RecordingProvider<P> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let data = self.inner.get(key).await;
+        let mut state = self.recording.state.borrow_mut();
+        state.events.push(Event::Request {
+            key: *key,
+            size: data.as_ref().map(Vec::len),
+        });
+        if let Some(data) = &data {
+            state.preimages.insert(*key, data.clone());
+        }
+        data
+    }
+}
+
+pub struct RecordingHintHandler<H> {
+    inner: H,
+    recording: Recording,
+}
+
+impl<H: HintHandler> HintHandler for RecordingHintHandler<H> {
+    fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()> {
+        self.inner.handle_hint(hint, preimages)
+    }
+
+    fn handle_raw_hint(&mut self, hint: &[u8]) {
+        self.recording
+            .state
+            .borrow_mut()
+            .events
+            .push(Event::Hint(hint.to_vec()));
+        self.inner.handle_raw_hint(hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hint_handler::LogHintHandler;
+    use crate::preimage_provider::DiskKvProvider;
+
+    #[tokio::test]
+    async fn test_events_are_recorded_and_used_preimages_exported() {
+        let recording = Recording::new();
+        let provider = recording.provider(HashMap::from([
+            ([1; 32], b"used".to_vec()),
+            ([2; 32], b"unused".to_vec()),
+        ]));
+        let mut handler = recording.hint_handler(LogHintHandler);
+
+        handler.handle_raw_hint(b"l1-block-header 0xab");
+        handler.handle_raw_hint(b"garbage");
+        provider.get(&[1; 32]).await;
+        provider.get(&[3; 32]).await;
+
+        assert_eq!(
+            recording.events(),
+            [
+                Event::Hint(b"l1-block-header 0xab".to_vec()),
+                Event::Hint(b"garbage".to_vec()),
+                Event::Request {
+                    key: [1; 32],
+                    size: Some(4)
+                },
+                Event::Request {
+                    key: [3; 32],
+                    size: None
+                },
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        recording
+            .write_preimages(&dir.path().join("preimages.json"), Format::Json)
+            .unwrap();
+        let exported: HashMap<String, String> = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join("preimages.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            exported,
+            HashMap::from([(hex::encode([1; 32]), hex::encode(b"used"))])
+        );
+
+        recording
+            .write_preimages(&dir.path().join("kv"), Format::DiskKv)
+            .unwrap();
+        assert_eq!(
+            DiskKvProvider::new(dir.path().join("kv"))
+                .get(&[1; 32])
+                .await,
+            Some(b"used".to_vec())
+        );
+
+        recording.write_log(&dir.path().join("log.json")).unwrap();
+        let log: Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.path().join("log.json")).unwrap())
+                .unwrap();
+        assert_eq!(log[0]["type"], "hint");
+        assert_eq!(log[0]["hint_type"], "l1-block-header");
+        assert_eq!(log[0]["data"], "0xab");
+        assert_eq!(log[1]["raw"], format!("0x{}", hex::encode(b"garbage")));
+        assert_eq!(log[1]["size"], 7);
+        assert_eq!(log[1]["hint_type"], Value::Null);
+        assert_eq!(log[3]["size"], Value::Null);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::error::MissingPreimageError;
+use crate::hint_handler::{Hint, HintHandler};
+use crate::preimage_provider::{AsyncPreimageProvider, PreimageStore};
+use anyhow::{bail, Context, Result};
+use log::{debug, error, warn};
+use std::cell::RefCell;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Hints name a type and a small amount of data, so a longer length can only come from a broken or hostile client
+pub const MAX_HINT_LENGTH: usize = 1024 * 1024;
+
+/// Serve preimage requests forwarded from the emulator on the reader channel until it is closed
+///
+/// On each received request try and open a pre-image and stream it to the guest on the writer channel.
+/// Either channel may transfer only a few bytes at a time so keys are read exactly and responses written in full.
+/// If `provider` does not have a requested preimage a [`MissingPreimageError`] is returned.
+pub async fn serve(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    provider: &(impl AsyncPreimageProvider + ?Sized),
+) -> Result<()> {
+    loop {
+        let mut key_buffer = [0; 32];
+        // the channel is only closed cleanly between requests, a key cut off part way through is an error
+        if reader.read(&mut key_buffer[..1]).await? == 0 {
+            debug!("Preimage channel closed");
+            return Ok(());
+        }
+        reader
+            .read_exact(&mut key_buffer[1..])
+            .await
+            .context("Preimage channel closed part way through a key")?;
+        debug!("Received key bytes: {:?}", &key_buffer);
+
+        if let Some(mut preimage) = provider.open(&key_buffer).await {
+            // first it needs to write the length as a u64 big-endian
+            writer.write_all(&preimage.len.to_be_bytes()).await?;
+
+            // then copy the actual data in chunks so it never needs to be held in memory all at once. Never more
+            // than was announced is sent, as the guest would read anything further as the next response
+            let written =
+                tokio::io::copy(&mut (&mut preimage.reader).take(preimage.len), &mut writer)
+                    .await?;
+            if written != preimage.len {
+                bail!(
+                    "Preimage for key 0x{} ended after {} of {} bytes",
+                    hex::encode(key_buffer),
+                    written,
+                    preimage.len
+                );
+            }
+            writer.flush().await?;
+        } else {
+            let e = MissingPreimageError { key: key_buffer };
+            error!("{}", e);
+            return Err(e.into());
+        }
+    }
+}
+
+/// Serve hints forwarded from the emulator on the reader channel until it is closed
+///
+/// Each hint is a big-endian u32 length prefix followed by the hint. Once the handler has processed a hint
+/// it is acknowledged by writing a single byte to the writer channel. Hints longer than [`MAX_HINT_LENGTH`] are an
+/// error.
+pub async fn serve_hints(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    mut handler: impl HintHandler,
+    preimages: &RefCell<impl PreimageStore>,
+) -> Result<()> {
+    loop {
+        let mut length_buffer = [0; 4];
+        // as with keys, only a close between hints is clean
+        if reader.read(&mut length_buffer[..1]).await? == 0 {
+            debug!("Hint channel closed");
+            return Ok(());
+        }
+        reader
+            .read_exact(&mut length_buffer[1..])
+            .await
+            .context("Hint channel closed part way through a hint length")?;
+        let length = u32::from_be_bytes(length_buffer) as usize;
+        if length > MAX_HINT_LENGTH {
+            bail!(
+                "Hint of {} bytes is longer than the maximum of {}",
+                length,
+                MAX_HINT_LENGTH
+            );
+        }
+        let mut hint = vec![0; length];
+        reader.read_exact(&mut hint).await?;
+
+        handler.handle_raw_hint(&hint);
+        match Hint::decode(&hint) {
+            Ok(hint) => handler.handle_hint(&hint, &mut *preimages.borrow_mut())?,
+            // still acknowledge so the guest is not left waiting
+            Err(e) => warn!("Ignoring hint that could not be decoded: {}", e),
+        }
+        writer.write_all(&[0]).await?;
+        writer.flush().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chaos::Fragmented;
+    use crate::preimage_provider::{FallbackProvider, PreimageProvider, PreimageStream};
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use tokio::io::duplex;
+    use tokio::net::UnixStream;
+
+    fn test_preimages() -> HashMap<[u8; 32], Vec<u8>> {
+        (0..8_u8)
+            .map(|i| {
+                let length = [0, 1, 31, 32, 33, 1000, 4096, 100_000][i as usize];
+                ([i; 32], (0..length).map(|b| (b % 251) as u8).collect())
+            })
+            .collect()
+    }
+
+    /// Act as the emulator: request every preimage in `preimages` over a fragmented socketpair and check the responses
+    async fn request_all(seed: u64, preimages: HashMap<[u8; 32], Vec<u8>>) -> Result<()> {
+        let (client, server) = UnixStream::pair()?;
+        let (server_reader, server_writer) = server.into_split();
+        let store = RefCell::new(preimages.clone());
+
+        let client = async move {
+            let (reader, writer) = client.into_split();
+            let mut reader = Fragmented::new(reader, seed, 7);
+            let mut writer = Fragmented::new(writer, seed.rotate_left(32), 5);
+            for (key, expected) in &preimages {
+                writer.write_all(key).await?;
+                let mut length = [0; 8];
+                reader.read_exact(&mut length).await?;
+                let mut data = vec![0; u64::from_be_bytes(length) as usize];
+                reader.read_exact(&mut data).await?;
+                assert_eq!(&data, expected);
+            }
+            // closing the request channel shuts down the server
+            drop(writer);
+            Ok(())
+        };
+        tokio::try_join!(
+            serve(
+                Fragmented::new(server_reader, seed.wrapping_mul(3), 11),
+                Fragmented::new(server_writer, seed.wrapping_mul(5), 13),
+                &store
+            ),
+            client
+        )?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_requests_with_fragmented_io() {
+        for seed in 1..20 {
+            request_all(seed, test_preimages()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_served_from_fallback() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let store = RefCell::new(HashMap::new());
+        let provider = FallbackProvider::new(&store, test_preimages());
+
+        let client = async move {
+            client.write_all(&[2; 32]).await?;
+            let mut response = [0; 8 + 31];
+            client.read_exact(&mut response).await?;
+            assert_eq!(response[..8], 31_u64.to_be_bytes());
+            Ok(())
+        };
+        tokio::try_join!(serve(server_reader, server_writer, &provider), client).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_preimage_is_an_error() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let store = RefCell::new(test_preimages());
+
+        client.write_all(&[0xee; 32]).await.unwrap();
+        let e = serve(server_reader, server_writer, &store)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<MissingPreimageError>(),
+            Some(&MissingPreimageError { key: [0xee; 32] })
+        );
+    }
+
+    /// Announces every preimage with the given length, whatever the length of its data
+    struct WrongLengthProvider(u64);
+
+    impl PreimageProvider for WrongLengthProvider {
+        fn get(&self, _key: &[u8; 32]) -> Option<Vec<u8>> {
+            Some(vec![1, 2, 3])
+        }
+
+        fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+            let mut preimage = PreimageStream::from(PreimageProvider::get(self, key)?);
+            preimage.len = self.0;
+            Some(preimage)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_shorter_than_length_is_an_error() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+
+        client.write_all(&[0x11; 32]).await.unwrap();
+        let e = serve(server_reader, server_writer, &WrongLengthProvider(4))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("ended after 3 of 4 bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_longer_than_length_is_cut_off() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+
+        let client = async move {
+            client.write_all(&[0x11; 32]).await.unwrap();
+            let mut response = [0; 10];
+            client.read_exact(&mut response).await.unwrap();
+            assert_eq!(response, [0, 0, 0, 0, 0, 0, 0, 2, 1, 2]);
+            // nothing follows the announced length
+            client.write_all(&[0x11; 32]).await.unwrap();
+            client.read_exact(&mut response).await.unwrap();
+            assert_eq!(response, [0, 0, 0, 0, 0, 0, 0, 2, 1, 2]);
+        };
+        let (result, _) = tokio::join!(
+            serve(server_reader, server_writer, &WrongLengthProvider(2)),
+            client
+        );
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_partial_key_then_eof_is_an_error() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let store = RefCell::new(test_preimages());
+
+        client.write_all(&[1; 10]).await.unwrap();
+        drop(client);
+        assert!(serve(server_reader, server_writer, &store).await.is_err());
+    }
+
+    #[derive(Default)]
+    struct RecordingHintHandler(Vec<Hint>, Vec<Vec<u8>>);
+
+    impl HintHandler for &mut RecordingHintHandler {
+        fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()> {
+            self.0.push(hint.clone());
+            // make the hinted data available under a key derived from it
+            preimages.insert([hint.data[0]; 32], hint.data.clone());
+            Ok(())
+        }
+
+        fn handle_raw_hint(&mut self, hint: &[u8]) {
+            self.1.push(hint.to_vec());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hints_are_acknowledged() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let mut handler = RecordingHintHandler::default();
+        let preimages = RefCell::new(HashMap::new());
+
+        let client = async move {
+            for hint in [&b"l1-block-header 0xabcd"[..], b"not a valid hint"] {
+                client.write_all(&(hint.len() as u32).to_be_bytes()).await?;
+                client.write_all(hint).await?;
+                let mut ack = [0xff; 1];
+                client.read_exact(&mut ack).await?;
+                assert_eq!(ack, [0]);
+            }
+            // closing the channel stops the server
+            drop(client);
+            Ok(())
+        };
+        tokio::try_join!(
+            serve_hints(server_reader, server_writer, &mut handler, &preimages),
+            client
+        )
+        .unwrap();
+
+        assert_eq!(
+            handler.0,
+            [Hint {
+                hint_type: "l1-block-header".to_string(),
+                data: vec![0xab, 0xcd]
+            }]
+        );
+        // hints that cannot be decoded are still received raw
+        assert_eq!(
+            handler.1,
+            [
+                b"l1-block-header 0xabcd".to_vec(),
+                b"not a valid hint".to_vec()
+            ]
+        );
+        assert_eq!(
+            PreimageProvider::get(&preimages.into_inner(), &[0xab; 32]),
+            Some(vec![0xab, 0xcd])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_hint_length_then_eof_is_an_error() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (server_reader, server_writer) = server.into_split();
+        let mut handler = RecordingHintHandler::default();
+        let preimages = RefCell::new(HashMap::new());
+
+        let mut client = Fragmented::new(client, 1, 1);
+        client.write_all(&[0, 0, 0]).await.unwrap();
+        drop(client);
+        assert!(serve_hints(
+            Fragmented::new(server_reader, 2, 1),
+            Fragmented::new(server_writer, 3, 1),
+            &mut handler,
+            &preimages
+        )
+        .await
+        .is_err());
+        assert!(handler.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_overlong_hint_is_an_error() {
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let mut handler = RecordingHintHandler::default();
+        let preimages = RefCell::new(HashMap::new());
+
+        client
+            .write_all(&(MAX_HINT_LENGTH as u32 + 1).to_be_bytes())
+            .await
+            .unwrap();
+        assert!(
+            serve_hints(server_reader, server_writer, &mut handler, &preimages)
+                .await
+                .is_err()
+        );
+        assert!(handler.1.is_empty());
+    }
+
+    /// Only serves its preimage once notified by the hint handler
+    struct WaitForHintProvider(std::rc::Rc<tokio::sync::Notify>);
+
+    #[async_trait(?Send)]
+    impl AsyncPreimageProvider for WaitForHintProvider {
+        async fn get(&self, _key: &[u8; 32]) -> Option<Vec<u8>> {
+            self.0.notified().await;
+            Some(b"fetched".to_vec())
+        }
+    }
+
+    struct NotifyHintHandler(std::rc::Rc<tokio::sync::Notify>);
+
+    impl HintHandler for NotifyHintHandler {
+        fn handle_hint(&mut self, _hint: &Hint, _preimages: &mut dyn PreimageStore) -> Result<()> {
+            self.0.notify_one();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hints_are_served_while_provider_awaits() {
+        let (mut hint_client, hint_server) = duplex(64);
+        let (hint_reader, hint_writer) = tokio::io::split(hint_server);
+        let (mut client, server) = duplex(64);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let notify = std::rc::Rc::new(tokio::sync::Notify::new());
+        let preimages = RefCell::new(HashMap::new());
+        let handler = NotifyHintHandler(notify.clone());
+        let provider = WaitForHintProvider(notify);
+
+        let client = async move {
+            // the provider cannot return until the hint has been handled
+            client.write_all(&[2; 32]).await?;
+            let hint = b"l1-block-header 0xabcd";
+            hint_client
+                .write_all(&(hint.len() as u32).to_be_bytes())
+                .await?;
+            hint_client.write_all(hint).await?;
+            let mut ack = [0xff; 1];
+            hint_client.read_exact(&mut ack).await?;
+
+            let mut response = [0; 8 + 7];
+            client.read_exact(&mut response).await?;
+            assert_eq!(&response[8..], b"fetched");
+            drop((client, hint_client));
+            Ok(())
+        };
+        tokio::try_join!(
+            serve_hints(hint_reader, hint_writer, handler, &preimages),
+            serve(server_reader, server_writer, &provider),
+            client
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! Statistics about the oracle traffic of a run
+//!
+//! Requests are counted per key type along with the bytes served, how many missed the main preimage store and
+//! how long the providers took to find them. Hints are counted per hint type. Together these show which data dominates a run.
+
+use crate::error::key_type_name;
+use crate::hint_handler::{Hint, HintHandler};
+use crate::preimage_provider::{AsyncPreimageProvider, PreimageStore, PreimageStream};
+use anyhow::Result;
+use async_trait::async_trait;
+use log::debug;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyTypeStats {
+    pub requests: u64,
+    /// Requests that were not found in the main preimage store and fell through to the fallback, on disk or remote
+    /// providers, whether or not those could serve them
+    pub misses: u64,
+    pub bytes: u64,
+    /// Time taken to find the preimages, not including sending them
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HintStats {
+    pub hints: u64,
+    pub bytes: u64,
+}
+
+struct StatsState {
+    started: Instant,
+    key_types: BTreeMap<u8, KeyTypeStats>,
+    hint_types: BTreeMap<String, HintStats>,
+}
+
+/// Shared statistics of a run. Wrap the provider and hint handler to count what passes through them
+#[derive(Clone)]
+pub struct Stats {
+    state: Rc<RefCell<StatsState>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(StatsState {
+                started: Instant::now(),
+                key_types: BTreeMap::new(),
+                hint_types: BTreeMap::new(),
+            })),
+        }
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count every request made to `provider`
+    pub fn provider<P: AsyncPreimageProvider>(&self, provider: P) -> StatsProvider<P> {
+        StatsProvider {
+            inner: provider,
+            stats: self.clone(),
+        }
+    }
+
+    /// Count every request made to `provider` as a miss. Wraps the providers consulted once a key is not found in
+    /// the main preimage store
+    pub fn miss_provider<P: AsyncPreimageProvider>(&self, provider: P) -> StatsMissProvider<P> {
+        StatsMissProvider {
+            inner: provider,
+            stats: self.clone(),
+        }
+    }
+
+    /// Count every hint passed to `handler`
+    pub fn hint_handler<H: HintHandler>(&self, handler: H) -> StatsHintHandler<H> {
+        StatsHintHandler {
+            inner: handler,
+            stats: self.clone(),
+        }
+    }
+
+    /// Statistics of every key type requested so far, by type byte
+    pub fn key_types(&self) -> BTreeMap<u8, KeyTypeStats> {
+        self.state.borrow().key_types.clone()
+    }
+
+    /// Statistics of every hint type received so far
+    pub fn hint_types(&self) -> BTreeMap<String, HintStats> {
+        self.state.borrow().hint_types.clone()
+    }
+
+    fn record_request(&self, key: &[u8; 32], len: Option<u64>, latency: Duration) {
+        let mut state = self.state.borrow_mut();
+        let stats = state.key_types.entry(key[0]).or_default();
+        stats.requests += 1;
+        stats.bytes += len.unwrap_or(0);
+        stats.total_latency += latency;
+        stats.max_latency = stats.max_latency.max(latency);
+    }
+
+    fn record_miss(&self, key: &[u8; 32]) {
+        self.state
+            .borrow_mut()
+            .key_types
+            .entry(key[0])
+            .or_default()
+            .misses += 1;
+    }
+
+    /// A summary of the run so far
+    pub fn report(&self) -> Value {
+        let state = self.state.borrow();
+        let key_types: serde_json::Map<String, Value> = state
+            .key_types
+            .iter()
+            .map(|(type_byte, stats)| {
+                let mean_latency = match stats.requests {
+                    0 => Duration::ZERO,
+                    requests => {
+                        Duration::from_secs_f64(stats.total_latency.as_secs_f64() / requests as f64)
+                    }
+                };
+                (
+                    key_type_name(*type_byte),
+                    json!({
+                        "requests": stats.requests,
+                        "misses": stats.misses,
+                        "bytes": stats.bytes,
+                        "mean_latency_ms": mean_latency.as_secs_f64() * 1000.0,
+                        "max_latency_ms": stats.max_latency.as_secs_f64() * 1000.0,
+                    }),
+                )
+            })
+            .collect();
+        let hint_types: serde_json::Map<String, Value> = state
+            .hint_types
+            .iter()
+            .map(|(hint_type, stats)| {
+                (
+                    hint_type.clone(),
+                    json!({ "hints": stats.hints, "bytes": stats.bytes }),
+                )
+            })
+            .collect();
+        json!({
+            "duration_secs": state.started.elapsed().as_secs_f64(),
+            "requests": state.key_types.values().map(|s| s.requests).sum::<u64>(),
+            "misses": state.key_types.values().map(|s| s.misses).sum::<u64>(),
+            "bytes": state.key_types.values().map(|s| s.bytes).sum::<u64>(),
+            "hints": state.hint_types.values().map(|s| s.hints).sum::<u64>(),
+            "key_types": key_types,
+            "hint_types": hint_types,
+        })
+    }
+
+    /// Log the summary at debug level every `interval`. Never completes
+    pub async fn log_every(&self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        // the first tick completes immediately, before anything has happened
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            debug!("Stats: {}", self.report());
+        }
+    }
+}
+
+pub struct StatsProvider<P> {
+    inner: P,
+    stats: Stats,
+}
+
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider> AsyncPreimageProvider for StatsProvider<P> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let start = Instant::now();
+        let data = self.inner.get(key).await;
+        let len = data.as_ref().map(|data| data.len() as u64);
+        self.stats.record_request(key, len, start.elapsed());
+        data
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        let start = Instant::now();
+        let preimage = self.inner.open(key).await;
+        let len = preimage.as_ref().map(|preimage| preimage.len);
+        self.stats.record_request(key, len, start.elapsed());
+        preimage
+    }
+}
+
+pub struct StatsMissProvider<P> {
+    inner: P,
+    stats: Stats,
+}
+
+#[async_trait(?Send)]
+impl<P: AsyncPreimageProvider> AsyncPreimageProvider for StatsMissProvider<P> {
+    async fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        // a miss is counted before the request it belongs to completes
+        self.stats.record_miss(key);
+        self.inner.get(key).await
+    }
+
+    async fn open<'a>(&'a self, key: &[u8; 32]) -> Option<PreimageStream<'a>> {
+        self.stats.record_miss(key);
+        self.inner.open(key).await
+    }
+}
+
+pub struct StatsHintHandler<H> {
+    inner: H,
+    stats: Stats,
+}
+
+impl<H: HintHandler> HintHandler for StatsHintHandler<H> {
+    fn handle_hint(&mut self, hint: &Hint, preimages: &mut dyn PreimageStore) -> Result<()> {
+        {
+            let mut state = self.stats.state.borrow_mut();
+            let stats = state.hint_types.entry(hint.hint_type.clone()).or_default();
+            stats.hints += 1;
+            stats.bytes += hint.data.len() as u64;
+        }
+        self.inner.handle_hint(hint, preimages)
+    }
+
+    fn handle_raw_hint(&mut self, hint: &[u8]) {
+        self.inner.handle_raw_hint(hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hint_handler::LogHintHandler;
+    use crate::preimage_provider::FallbackProvider;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_requests_and_hints_are_counted() {
+        let stats = Stats::new();
+        let mut keccak_key = [1; 32];
+        keccak_key[0] = 2;
+        let mut lazy_key = [3; 32];
+        lazy_key[0] = 2;
+        let provider = stats.provider(FallbackProvider::new(
+            HashMap::from([(keccak_key, vec![0; 10])]),
+            stats.miss_provider(HashMap::from([(lazy_key, vec![0; 5])])),
+        ));
+        let mut handler = stats.hint_handler(LogHintHandler);
+
+        let hint = Hint {
+            hint_type: "l1-block-header".to_string(),
+            data: vec![0xab; 32],
+        };
+        handler.handle_hint(&hint, &mut HashMap::new()).unwrap();
+        handler.handle_hint(&hint, &mut HashMap::new()).unwrap();
+        provider.open(&keccak_key).await.unwrap();
+        provider.get(&keccak_key).await.unwrap();
+        provider.get(&lazy_key).await.unwrap();
+        assert!(provider.get(&[2; 32]).await.is_none());
+
+        let keccak = &stats.key_types()[&2];
+        assert_eq!(keccak.requests, 4);
+        assert_eq!(keccak.misses, 2);
+        assert_eq!(keccak.bytes, 25);
+        assert_eq!(
+            stats.hint_types()["l1-block-header"],
+            HintStats {
+                hints: 2,
+                bytes: 64
+            }
+        );
+
+        let report = stats.report();
+        assert_eq!(report["requests"], 4);
+        assert_eq!(report["hints"], 2);
+        assert_eq!(report["key_types"]["Keccak256"]["bytes"], 25);
+    }
+}
@@ -0,0 +1,338 @@
+//! Load and save whole preimage stores in any of the formats the server reads
+//!
+//! - [`Format::Json`]: a json object mapping hex keys to hex values, without `0x` prefixes
+//! - [`Format::Directory`]: a directory of files named with their hex key, as written by `--rpc-cache-dir`
+//! - [`Format::DiskKv`]: a directory in the op-program disk key-value format
+//! - [`Format::Archive`]: a preimage archive written by [`write_archive`]
+
+use crate::preimage_provider::{
+    is_archive, write_archive, ArchiveProvider, DiskKvProvider, PreimageProvider,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+pub type Preimages = HashMap<[u8; 32], Vec<u8>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Directory,
+    DiskKv,
+    Archive,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "dir" => Ok(Self::Directory),
+            "disk-kv" => Ok(Self::DiskKv),
+            "archive" => Ok(Self::Archive),
+            _ => bail!(
+                "Unknown store format {}, expected json, dir, disk-kv or archive",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Directory => "dir",
+            Self::DiskKv => "disk-kv",
+            Self::Archive => "archive",
+        })
+    }
+}
+
+impl Format {
+    /// The format of an existing store, or `None` for a directory without any preimages, which could be either
+    /// directory format
+    ///
+    /// Directories are told apart by how their files are named: `0x` prefixed keys with a `.txt` extension for
+    /// op-program disk key-value stores and bare keys otherwise. Files named in neither way are ignored, and a
+    /// directory with files named in both ways is an error.
+    pub fn detect(path: &Path) -> Result<Option<Self>> {
+        if !path.is_dir() {
+            return Ok(Some(if is_archive(path) {
+                Self::Archive
+            } else {
+                Self::Json
+            }));
+        }
+        let (mut directory, mut disk_kv) = (false, false);
+        for entry in
+            std::fs::read_dir(path).with_context(|| format!("Unable to read {}", path.display()))?
+        {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if let Some(key) = name
+                .strip_prefix("0x")
+                .and_then(|name| name.strip_suffix(".txt"))
+            {
+                disk_kv |= parse_key(key).is_ok();
+            } else {
+                directory |= parse_key(name).is_ok();
+            }
+        }
+        match (directory, disk_kv) {
+            (true, true) => bail!(
+                "{} contains preimages in both the dir and disk-kv formats, pass --format",
+                path.display()
+            ),
+            (true, false) => Ok(Some(Self::Directory)),
+            (false, true) => Ok(Some(Self::DiskKv)),
+            (false, false) => Ok(None),
+        }
+    }
+
+    /// Like [`Format::detect`] but a directory without any preimages is an error
+    pub fn detect_strict(path: &Path) -> Result<Self> {
+        Self::detect(path)?.ok_or_else(|| {
+            anyhow!(
+                "{} contains no preimages so its format cannot be detected, pass --format",
+                path.display()
+            )
+        })
+    }
+
+    /// The format of an existing store, or the format implied by the extension of a new one: `.json` for json,
+    /// `.bin` for an archive and a directory otherwise. An existing directory without any preimages is an error
+    pub fn infer(path: &Path) -> Result<Self> {
+        if path.exists() {
+            return Self::detect_strict(path);
+        }
+        Ok(match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::Json,
+            Some("bin") => Self::Archive,
+            _ => Self::Directory,
+        })
+    }
+}
+
+/// Load every preimage in the store at `path`
+pub fn load(path: &Path) -> Result<Preimages> {
+    match Format::detect(path)? {
+        Some(format) => load_as(path, format),
+        None => Ok(Preimages::new()),
+    }
+}
+
+/// Load every preimage in the store at `path`, which is in `format`
+pub fn load_as(path: &Path, format: Format) -> Result<Preimages> {
+    let preimages = match format {
+        Format::Json => {
+            let json = std::fs::read_to_string(path)
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            from_json_str(&json)?
+        }
+        Format::Directory => read_dir_entries(path, "", |file| Ok(std::fs::read(file)?))?,
+        Format::DiskKv => read_dir_entries(path, ".txt", |file| {
+            Ok(hex::decode(std::fs::read_to_string(file)?.trim())?)
+        })?,
+        Format::Archive => {
+            let archive = ArchiveProvider::open(path)?;
+            archive
+                .keys()
+                .map(|key| {
+                    let value = archive
+                        .get(&key)
+                        .ok_or_else(|| anyhow!("Unable to read 0x{}", hex::encode(key)))?;
+                    Ok((key, value))
+                })
+                .collect::<Result<_>>()?
+        }
+    };
+    debug!(
+        "Loaded {} preimages from {} ({})",
+        preimages.len(),
+        path.display(),
+        format
+    );
+    Ok(preimages)
+}
+
+/// The key and preimage length of every preimage in the store at `path`, sorted by key
+///
+/// Only json stores are loaded. The lengths of the preimages in other stores come from file sizes or the archive
+/// index without reading the values.
+pub fn list(path: &Path) -> Result<Vec<([u8; 32], u64)>> {
+    let Some(format) = Format::detect(path)? else {
+        return Ok(Vec::new());
+    };
+    let mut entries: Vec<([u8; 32], u64)> = match format {
+        Format::Json => load(path)?
+            .into_iter()
+            .map(|(key, value)| (key, value.len() as u64))
+            .collect(),
+        Format::Directory => read_dir_entries(path, "", |file| Ok(file.metadata()?.len()))?,
+        // values are hex encoded with two characters per byte
+        Format::DiskKv => read_dir_entries(path, ".txt", |file| Ok(file.metadata()?.len() / 2))?,
+        Format::Archive => ArchiveProvider::open(path)?.entries().collect(),
+    };
+    entries.sort();
+    Ok(entries)
+}
+
+/// Parse a preimage json object
+pub fn from_json_str(json: &str) -> Result<Preimages> {
+    let json: HashMap<String, String> = serde_json::from_str(json)?;
+    json.iter()
+        .map(|(key, value)| Ok((parse_key(key)?, hex::decode(value)?)))
+        .collect()
+}
+
+/// Read every file in `dir` named with a key followed by `suffix`. Files without the suffix are skipped
+fn read_dir_entries<T, C: FromIterator<([u8; 32], T)>>(
+    dir: &Path,
+    suffix: &str,
+    read: impl Fn(&Path) -> Result<T>,
+) -> Result<C> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Unable to read directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        // left behind by a write that was interrupted before its file was renamed into place
+        if name.map_or(false, |name| name.ends_with(".tmp")) {
+            continue;
+        }
+        let Some(key) = name.and_then(|name| name.strip_suffix(suffix)) else {
+            continue;
+        };
+        let key = parse_key(key)
+            .with_context(|| format!("{} is not named with a key", path.display()))?;
+        let value = read(&path).with_context(|| format!("Unable to read {}", path.display()))?;
+        entries.push((key, value));
+    }
+    Ok(entries.into_iter().collect())
+}
+
+/// Write `preimages` to a store at `path`
+///
+/// Preimages are added to an existing directory store. Any other existing store is replaced.
+pub fn save(path: &Path, format: Format, preimages: &Preimages, compress: bool) -> Result<()> {
+    match format {
+        Format::Json => {
+            let json: BTreeMap<String, String> = preimages
+                .iter()
+                .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+                .collect();
+            std::fs::write(path, serde_json::to_string_pretty(&json)?)?;
+        }
+        Format::Directory => {
+            std::fs::create_dir_all(path)?;
+            for (key, value) in preimages {
+                std::fs::write(path.join(hex::encode(key)), value)?;
+            }
+        }
+        Format::DiskKv => {
+            std::fs::create_dir_all(path)?;
+            let store = DiskKvProvider::new(path);
+            for (key, value) in preimages {
+                store.put(key, value)?;
+            }
+        }
+        Format::Archive => write_archive(path, preimages, compress)?,
+    }
+    Ok(())
+}
+
+/// Parse a 32 byte hex key, with or without a `0x` prefix
+pub fn parse_key(key: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(key.trim_start_matches("0x"))?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("Expected a 32 byte key, got {}", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let preimages = Preimages::from([([1; 32], b"one".to_vec()), ([2; 32], Vec::new())]);
+
+        for (name, format) in [
+            ("preimages.json", Format::Json),
+            ("preimages", Format::Directory),
+            ("disk-kv", Format::DiskKv),
+            ("preimages.bin", Format::Archive),
+        ] {
+            let path = dir.path().join(name);
+            save(&path, format, &preimages, true).unwrap();
+            assert_eq!(Format::detect(&path).unwrap(), Some(format), "{}", name);
+            assert_eq!(load(&path).unwrap(), preimages, "{}", name);
+            assert_eq!(
+                list(&path).unwrap(),
+                [([1; 32], 3), ([2; 32], 0)],
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_infer_new_stores_from_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            Format::infer(&dir.path().join("a.json")).unwrap(),
+            Format::Json
+        );
+        assert_eq!(
+            Format::infer(&dir.path().join("a.bin")).unwrap(),
+            Format::Archive
+        );
+        assert_eq!(
+            Format::infer(&dir.path().join("a")).unwrap(),
+            Format::Directory
+        );
+        assert_eq!("disk-kv".parse::<Format>().unwrap(), Format::DiskKv);
+    }
+
+    #[test]
+    fn test_detect_directories_from_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = "ab".repeat(32);
+
+        // an empty directory could be either format
+        assert_eq!(Format::detect(dir.path()).unwrap(), None);
+        assert!(Format::infer(dir.path()).is_err());
+        assert!(load(dir.path()).unwrap().is_empty());
+
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        assert_eq!(Format::detect(dir.path()).unwrap(), None);
+
+        std::fs::write(dir.path().join(&key), "").unwrap();
+        assert_eq!(Format::detect(dir.path()).unwrap(), Some(Format::Directory));
+
+        std::fs::write(dir.path().join(format!("0x{}.txt", key)), "").unwrap();
+        assert!(Format::detect(dir.path()).is_err());
+
+        std::fs::remove_file(dir.path().join(&key)).unwrap();
+        assert_eq!(Format::detect(dir.path()).unwrap(), Some(Format::DiskKv));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(
+            parse_key(&format!("0x{}", "ab".repeat(32))).unwrap(),
+            [0xab; 32]
+        );
+        assert_eq!(parse_key(&"cd".repeat(32)).unwrap(), [0xcd; 32]);
+        assert!(parse_key("0xabcd").is_err());
+    }
+}